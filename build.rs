@@ -1,6 +1,30 @@
-use std::env;
+use std::{env, path::PathBuf, process::Command};
+
+const PROTOS: [&str; 3] = [
+    "proto/atwany.proto",
+    "proto/health.proto",
+    "proto/reflection.proto",
+];
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    println!("cargo:rerun-if-changed=proto/atwany.proto");
+    for proto in &PROTOS {
+        println!("cargo:rerun-if-changed={}", proto);
+    }
+    // served by reflection, so every target needs it
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+    let status = Command::new(prost_build::protoc())
+        .arg("--include_imports")
+        .arg("-I")
+        .arg("proto")
+        .arg("-I")
+        .arg(prost_build::protoc_include())
+        .arg("-o")
+        .arg(out_dir.join("atwany_descriptor.bin"))
+        .args(PROTOS)
+        .status()?;
+    if !status.success() {
+        return Err(format!("protoc failed with {}", status).into());
+    }
     let current_env = env::var("CARGO_CFG_TARGET_ENV")?;
     println!("Current Target env={}", current_env);
     if current_env.to_lowercase() == "musl" {
@@ -12,6 +36,6 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .format(true)
         .build_server(true)
         .build_client(false)
-        .compile(&["proto/atwany.proto"], &["proto"])?;
+        .compile(&PROTOS, &["proto"])?;
     Ok(())
 }
@@ -0,0 +1,808 @@
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::pb::atwany::media::{MimeType, Size};
+
+/// Runtime configuration, read from the TOML file pointed at by
+/// `ATWANY_CONFIG` (defaults to `atwany.toml`). Every section is optional and
+/// falls back to the values the service used to hard-code.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub placeholder: PlaceholderConfig,
+    pub pool: PoolConfig,
+    pub original: OriginalConfig,
+    pub encoding: EncodingConfig,
+    pub png: PngConfig,
+    pub storage: StorageConfig,
+    pub reprocess: ReprocessConfig,
+    pub http: HttpConfig,
+    pub auth: AuthConfig,
+    pub grpc: GrpcConfig,
+    pub limits: LimitsConfig,
+    /// Applies to media without a tenant, and to tenants that don't set
+    /// their own.
+    pub policy: PolicyConfig,
+    /// Keyed by tenant name, see [`TenantConfig`].
+    pub tenants: BTreeMap<String, TenantConfig>,
+}
+
+/// A team sharing the deployment. Its media is stored under
+/// `tenants/<name>` in every storage directory, so ids never collide with
+/// other tenants', and its URLs start with `/t/<name>`. A call acts for the
+/// tenant its API key or JWT is bound to, or else for the one named in the
+/// `x-atwany-tenant` metadata. Sections left out inherit the top-level ones.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TenantConfig {
+    pub policy: Option<PolicyConfig>,
+    pub encoding: Option<EncodingConfig>,
+    pub png: Option<PngConfig>,
+}
+
+/// What uploads are accepted.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PolicyConfig {
+    /// Largest upload, in bytes, 0 accepts any size.
+    pub max_upload_bytes: u64,
+    /// Image formats `Upload` and `UploadAndWrite` accept, e.g. `jpeg`,
+    /// `png`, `gif`, `webp`. Empty accepts any decodable image.
+    pub allowed_formats: Vec<String>,
+    /// Extensions `UploadFile` accepts, empty accepts any.
+    pub allowed_file_extensions: Vec<String>,
+    pub quota: QuotaConfig,
+}
+
+/// Limits on what a tenant stores and the image processing it uses, 0 is
+/// unlimited. Crossing a soft limit is reported in the
+/// `x-atwany-quota-warning` response metadata, writes that would cross a
+/// hard one fail with `ResourceExhausted`.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct QuotaConfig {
+    /// Bytes of every stored variant, manifest and file.
+    pub soft_bytes: u64,
+    pub hard_bytes: u64,
+    /// Number of stored variants, manifests and files.
+    pub soft_objects: u64,
+    pub hard_objects: u64,
+    /// Seconds spent decoding, resizing and encoding on the worker pool.
+    pub soft_cpu_seconds: u64,
+    pub hard_cpu_seconds: u64,
+}
+
+/// Keeps clients from sending more than the service can take.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// Calls one API key or JWT subject can make, anonymous calls share a
+    /// single bucket.
+    pub per_principal: RateLimit,
+    /// Calls all principals of a tenant can make together.
+    pub per_tenant: RateLimit,
+    pub shedding: SheddingConfig,
+}
+
+/// A token bucket refilled with `rate` calls a second and holding up to
+/// `burst` of them. Calls finding it empty fail with `ResourceExhausted` and
+/// a `retry-after` in the metadata.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct RateLimit {
+    /// 0 is unlimited, otherwise at least [`MIN_RATE`].
+    pub rate: f64,
+    /// 0 allows one second worth of calls.
+    pub burst: u32,
+}
+
+/// Slowest refill allowed, keeping the `retry-after` of an empty bucket
+/// within a few minutes.
+const MIN_RATE: f64 = 0.01;
+
+impl RateLimit {
+    fn validate(&self, name: &str) -> anyhow::Result<()> {
+        let rate = self.rate;
+        if rate != 0.0 && !(rate.is_finite() && rate >= MIN_RATE) {
+            anyhow::bail!(
+                "{}.rate must be 0 or at least {}, not {}",
+                name,
+                MIN_RATE,
+                rate
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Turns calls that need the worker pool away once it, or the memory of
+/// the process, gets close to full. Past `threshold` the share of calls
+/// that are shed grows with the load, up to every call when full.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct SheddingConfig {
+    pub enabled: bool,
+    /// Load from which calls are shed, as a fraction of the running and
+    /// queued jobs the pool takes, or of `max_memory_bytes`.
+    pub threshold: f64,
+    /// Resident memory the process should stay under, 0 only looks at the
+    /// pool.
+    pub max_memory_bytes: u64,
+    /// Suggested to shed clients in the `retry-after` metadata.
+    pub retry_after_ms: u64,
+}
+
+impl Default for SheddingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            threshold: 0.75,
+            max_memory_bytes: 0,
+            retry_after_ms: 1000,
+        }
+    }
+}
+
+/// The gRPC listener.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct GrpcConfig {
+    pub addr: String,
+    pub tls: TlsConfig,
+    /// How often storage and the worker pool are checked for
+    /// `grpc.health.v1.Health`.
+    pub health_interval_secs: u64,
+    /// Serves `grpc.reflection.v1alpha.ServerReflection`, so tools like
+    /// grpcurl work without the proto files.
+    pub reflection: bool,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        Self {
+            addr: "0.0.0.0:50051".to_string(),
+            tls: TlsConfig::default(),
+            health_interval_secs: 5,
+            reflection: true,
+        }
+    }
+}
+
+/// PEM files for serving gRPC over TLS. The files are watched and new
+/// connections pick up a renewed certificate, open ones keep theirs.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    pub enabled: bool,
+    /// Certificate chain, leaf first.
+    pub cert_file: PathBuf,
+    /// PKCS#8 or RSA private key.
+    pub key_file: PathBuf,
+    /// CAs client certificates must chain to. Set, it turns on mutual TLS
+    /// and clients without a valid certificate are refused.
+    pub client_ca_file: Option<PathBuf>,
+    /// How often the files are checked for changes, 0 never reloads them.
+    pub reload_interval_secs: u64,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cert_file: PathBuf::from("/etc/atwany/tls/server.crt"),
+            key_file: PathBuf::from("/etc/atwany/tls/server.key"),
+            client_ca_file: None,
+            reload_interval_secs: 60,
+        }
+    }
+}
+
+/// Authentication of gRPC calls. Callers send an API key in the `x-api-key`
+/// metadata or a JWT as `authorization: Bearer <token>`, anything else is
+/// rejected with `Unauthenticated` once enabled.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub enabled: bool,
+    pub api_keys: Vec<ApiKey>,
+    pub jwt: JwtConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiKey {
+    /// Who the key belongs to, the principal of its calls.
+    pub name: String,
+    pub key: String,
+    /// Binds the key to a tenant, its calls can't act for another one.
+    pub tenant: Option<String>,
+}
+
+/// JWTs are accepted when signed with `secret` (HS256) or one of the RSA
+/// keys of `jwks_file` (RS256), and carry an `exp` claim. Their `sub` is the
+/// principal.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JwtConfig {
+    /// HS256 secret, empty rejects HS256 tokens.
+    pub secret: String,
+    /// JSON Web Key Set with the RS256 public keys, read at startup. Keys are
+    /// matched on the token's `kid`.
+    pub jwks_file: Option<PathBuf>,
+    /// Required `iss` claim, if set.
+    pub issuer: Option<String>,
+    /// Accepted `aud` claims, empty skips the check.
+    pub audience: Vec<String>,
+    /// Clock skew tolerated on `exp`.
+    pub leeway_secs: u64,
+    /// Claim binding the token to a tenant, when present.
+    pub tenant_claim: String,
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        Self {
+            secret: String::new(),
+            jwks_file: None,
+            issuer: None,
+            audience: Vec::new(),
+            leeway_secs: 60,
+            tenant_claim: "tenant".to_string(),
+        }
+    }
+}
+
+/// The HTTP server that serves stored media.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub enabled: bool,
+    pub addr: String,
+    /// Origins allowed to read media cross-origin, `*` allows any. Empty
+    /// sends no CORS headers.
+    pub cors_origins: Vec<String>,
+    /// Sent with every media response. Variants never change once written,
+    /// so they can be cached for good.
+    pub cache_control: String,
+    /// Formats `/media` URLs may answer with instead of a variant's own,
+    /// in order of preference, when the `Accept` header lists them.
+    pub negotiate: Vec<OutputFormat>,
+    pub transform: TransformConfig,
+    pub signing: SigningConfig,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            addr: "0.0.0.0:8081".to_string(),
+            cors_origins: Vec::new(),
+            cache_control: "public, max-age=31536000, immutable".to_string(),
+            negotiate: vec![OutputFormat::Avif, OutputFormat::Webp],
+            transform: TransformConfig::default(),
+            signing: SigningConfig::default(),
+        }
+    }
+}
+
+/// Expiring URLs for private media, see `UploadRequest.private`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SigningConfig {
+    /// The first key signs, all of them verify. Rotating means adding the
+    /// new key in front and dropping the old one once the URLs it signed
+    /// have expired.
+    pub keys: Vec<SigningKey>,
+    /// How long issued URLs stay valid.
+    pub ttl_secs: u64,
+    /// Prefixed to issued URLs, e.g. `https://media.example.com`. Empty
+    /// issues paths.
+    pub base_url: String,
+}
+
+impl Default for SigningConfig {
+    fn default() -> Self {
+        Self {
+            keys: Vec::new(),
+            ttl_secs: 3600,
+            base_url: String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SigningKey {
+    /// Sent as `kid` so the verifying key is known without trying them all.
+    pub id: String,
+    pub secret: String,
+}
+
+/// `/img/<id>?w=&h=&fit=&fmt=&q=`, renditions of the original on request,
+/// off unless enabled. Unsigned requests are limited to the allow-lists, an
+/// empty list allows any value. A URL signed with `signing_key` may ask for
+/// anything up to `max_dimension`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TransformConfig {
+    pub enabled: bool,
+    pub max_dimension: u32,
+    pub widths: Vec<u32>,
+    pub heights: Vec<u32>,
+    pub formats: Vec<OutputFormat>,
+    pub qualities: Vec<u8>,
+    /// Used when the URL has no `q`.
+    pub default_quality: u8,
+    /// HMAC-SHA256 key for the `sig` parameter, empty disables signing.
+    pub signing_key: String,
+    /// Reject unsigned URLs instead of checking the allow-lists.
+    pub require_signature: bool,
+    /// Rendered images are kept here.
+    pub cache_dir: PathBuf,
+    /// Bytes kept in `cache_dir`, the oldest renditions are removed past
+    /// it. 0 keeps everything.
+    pub cache_max_bytes: u64,
+}
+
+impl Default for TransformConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_dimension: 4096,
+            widths: vec![160, 320, 640, 960, 1280, 1920],
+            heights: vec![160, 320, 640, 960, 1280, 1920],
+            formats: vec![
+                OutputFormat::Jpeg,
+                OutputFormat::Png,
+                OutputFormat::Webp,
+            ],
+            qualities: vec![60, 80, 90],
+            default_quality: 80,
+            signing_key: String::new(),
+            require_signature: false,
+            cache_dir: PathBuf::from("/cache"),
+            cache_max_bytes: 1 << 30,
+        }
+    }
+}
+
+/// Backfilling variants after a preset change, see `Reprocess`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReprocessConfig {
+    /// Pause between two media, keeps the worker pool free for uploads.
+    pub throttle_ms: u64,
+}
+
+impl Default for ReprocessConfig {
+    fn default() -> Self { Self { throttle_ms: 50 } }
+}
+
+/// Where variants are written.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub layout: StorageLayout,
+    pub images_dir: PathBuf,
+    /// Where `UploadFile` stores files.
+    pub files_dir: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageLayout {
+    /// `<images_dir>/<id>_<suffix>.<ext>`
+    Flat,
+    /// `<images_dir>/ab/cd/<id>/<suffix>.<ext>`, `abcd` being the start of
+    /// the SHA-256 of the id, so no directory grows past a few thousand
+    /// entries.
+    Sharded,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            layout: StorageLayout::Flat,
+            images_dir: PathBuf::from("/images"),
+            files_dir: PathBuf::from("/files"),
+        }
+    }
+}
+
+/// Post-processing of PNG variants and of PNG files stored by `UploadFile`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PngConfig {
+    /// Lossless filter and deflate search.
+    pub optimize: bool,
+    /// oxipng preset, 0 (fast) to 6 (smallest).
+    pub level: u8,
+    /// Lossy quantization to a dithered palette of at most 256 colors, only
+    /// built with the `quantize` cargo feature.
+    pub quantize: bool,
+    pub quality_min: u8,
+    pub quality_max: u8,
+    /// 0.0 disables dithering, 1.0 is full Floyd-Steinberg.
+    pub dithering: f32,
+    /// Quantizer speed, 1 (best) to 10 (fastest).
+    pub speed: u8,
+    /// Also optimize `.png` files stored through `UploadFile`.
+    pub files: bool,
+}
+
+impl Default for PngConfig {
+    fn default() -> Self {
+        Self {
+            optimize: true,
+            level: 2,
+            quantize: false,
+            quality_min: 65,
+            quality_max: 90,
+            dithering: 1.0,
+            speed: 4,
+            files: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EncodingConfig {
+    pub mode: EncodeMode,
+    /// Quality used by the `fixed` mode.
+    pub quality: u8,
+    /// Bounds of the quality search done by the adaptive modes.
+    pub min_quality: u8,
+    pub max_quality: u8,
+    /// SSIM every variant has to reach in the `quality` mode.
+    pub target_ssim: f64,
+    /// Byte budget per variant in the `size` mode, keyed by `original`,
+    /// `medium`, `small`, `thumbnail` and `placeholder`.
+    pub target_bytes: BTreeMap<String, usize>,
+    /// JPEG encoder settings, unless a variant overrides them.
+    pub jpeg: JpegConfig,
+    /// Per variant overrides, keyed like `target_bytes`.
+    pub variants: BTreeMap<String, VariantConfig>,
+    pub generation: Generation,
+    /// AVIF encoder speed, 1 (smallest) to 10 (fastest).
+    pub avif_speed: u8,
+}
+
+/// When the resized variants are produced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Generation {
+    /// All of them at upload.
+    #[default]
+    Eager,
+    /// Only the original and the placeholder at upload, the others the first
+    /// time they are fetched.
+    Lazy,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct VariantConfig {
+    pub format: OutputFormat,
+    pub jpeg: Option<JpegConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JpegConfig {
+    pub encoder: JpegEncoderKind,
+    /// The settings below only apply to the `mozjpeg` encoder, which always
+    /// uses trellis quantization.
+    pub progressive: bool,
+    /// Optimized Huffman tables.
+    pub optimize_coding: bool,
+    pub chroma_subsampling: ChromaSubsampling,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JpegEncoderKind {
+    /// `image`'s baseline encoder.
+    Baseline,
+    Mozjpeg,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum ChromaSubsampling {
+    #[serde(rename = "420")]
+    S420,
+    #[serde(rename = "422")]
+    S422,
+    #[serde(rename = "444")]
+    S444,
+}
+
+impl Default for JpegConfig {
+    fn default() -> Self {
+        Self {
+            encoder: JpegEncoderKind::Baseline,
+            progressive: true,
+            optimize_coding: true,
+            chroma_subsampling: ChromaSubsampling::S420,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Jpeg,
+    Png,
+    Webp,
+    Avif,
+}
+
+impl OutputFormat {
+    pub const fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "jpeg",
+            OutputFormat::Png => "png",
+            OutputFormat::Webp => "webp",
+            OutputFormat::Avif => "avif",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EncodeMode {
+    /// Every variant at `quality`.
+    Fixed,
+    /// The highest quality that fits the variant's byte budget.
+    Size,
+    /// The lowest quality that reaches `target_ssim`.
+    Quality,
+}
+
+impl Default for EncodingConfig {
+    fn default() -> Self {
+        let target_bytes = [
+            ("original", 300_000),
+            ("medium", 80_000),
+            ("small", 30_000),
+            ("thumbnail", 12_000),
+            ("placeholder", 2_000),
+        ]
+        .iter()
+        .map(|(size, bytes)| (size.to_string(), *bytes))
+        .collect();
+        Self {
+            mode: EncodeMode::Fixed,
+            quality: 20,
+            min_quality: 10,
+            max_quality: 95,
+            target_ssim: 0.97,
+            target_bytes,
+            jpeg: JpegConfig::default(),
+            variants: BTreeMap::new(),
+            generation: Generation::Eager,
+            avif_speed: 6,
+        }
+    }
+}
+
+impl EncodingConfig {
+    pub fn target_bytes_for(&self, size: Size) -> usize {
+        self.target_bytes
+            .get(size_key(size))
+            .copied()
+            .unwrap_or(usize::MAX)
+    }
+
+    pub fn variant(&self, size: Size) -> VariantConfig {
+        self.variants
+            .get(size_key(size))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn jpeg_for(&self, size: Size) -> JpegConfig {
+        self.variant(size).jpeg.unwrap_or_else(|| self.jpeg.clone())
+    }
+}
+
+/// Name a variant goes by in the config file.
+pub const fn size_key(size: Size) -> &'static str {
+    match size {
+        Size::Original => "original",
+        Size::Placeholder => "placeholder",
+        Size::Thumbnail => "thumbnail",
+        Size::Small => "small",
+        Size::Medium => "medium",
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct OriginalConfig {
+    pub mode: OriginalMode,
+}
+
+/// How the `ORIGINAL` variant is stored.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OriginalMode {
+    /// Decode every pixel and store the upload re-encoded like the other
+    /// variants.
+    #[default]
+    Reencode,
+    /// Store the upload byte for byte. Only the resized variants need
+    /// pixels, so large JPEGs are decoded at 1/2, 1/4 or 1/8 scale.
+    Passthrough,
+}
+
+/// Bounds the blocking pool that decodes, resizes, encodes and hashes images.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PoolConfig {
+    /// Jobs running at once, `0` uses the number of CPUs.
+    pub workers: usize,
+    /// Jobs allowed to wait for a worker before new ones are rejected with
+    /// `ResourceExhausted`.
+    pub max_queue: usize,
+    /// How often the queue depth is logged, `0` disables it.
+    pub metrics_interval_secs: u64,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            workers: 0,
+            max_queue: 64,
+            metrics_interval_secs: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PlaceholderConfig {
+    /// Generate a BlurHash string for every `UploadAndWrite`.
+    pub blur_hash: bool,
+    /// Generate a ThumbHash alongside (or instead of) the BlurHash.
+    pub thumb_hash: bool,
+    pub x_components: u32,
+    pub y_components: u32,
+    /// When set, the larger of `x_components`/`y_components` is used along
+    /// the longer edge and the other axis is scaled down by the aspect ratio.
+    pub aspect_aware: bool,
+    /// Format of the data URI returned when an upload asks for an inline
+    /// placeholder.
+    pub inline_format: PlaceholderFormat,
+    pub inline_width: u32,
+    pub svg: SvgPlaceholderConfig,
+}
+
+/// SQIP style SVG placeholders built from blurred geometric primitives.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SvgPlaceholderConfig {
+    /// Generate the SVG for every upload, otherwise only when the request
+    /// asks for it.
+    pub enabled: bool,
+    pub shapes: u32,
+    pub shape: SvgShape,
+    /// Hard limit on the time spent fitting shapes, whatever was found so
+    /// far is returned once it runs out.
+    pub time_budget_ms: u64,
+    /// The image is downscaled to this size before fitting.
+    pub work_size: u32,
+    pub blur: f32,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SvgShape {
+    Triangle,
+    Ellipse,
+    Mixed,
+}
+
+impl Default for SvgPlaceholderConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            shapes: 12,
+            shape: SvgShape::Mixed,
+            time_budget_ms: 200,
+            work_size: 128,
+            blur: 12.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaceholderFormat {
+    Png,
+    Webp,
+}
+
+impl Default for PlaceholderConfig {
+    fn default() -> Self {
+        Self {
+            blur_hash: true,
+            thumb_hash: true,
+            x_components: 4,
+            y_components: 3,
+            aspect_aware: true,
+            inline_format: PlaceholderFormat::Png,
+            inline_width: 32,
+            svg: SvgPlaceholderConfig::default(),
+        }
+    }
+}
+
+impl From<PlaceholderFormat> for MimeType {
+    fn from(format: PlaceholderFormat) -> Self {
+        match format {
+            PlaceholderFormat::Png => MimeType::Png,
+            PlaceholderFormat::Webp => MimeType::Webp,
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> anyhow::Result<Self> {
+        let path = env::var("ATWANY_CONFIG")
+            .unwrap_or_else(|_| "atwany.toml".to_string());
+        if !Path::new(&path).exists() {
+            return Ok(Self::default());
+        }
+        let raw = fs::read_to_string(&path)?;
+        let config: Self = toml::from_str(&raw)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Rejects settings this build can't honor.
+    fn validate(&self) -> anyhow::Result<()> {
+        let quantize = self.png.quantize
+            || self.tenants.values().any(|tenant| {
+                tenant.png.as_ref().map_or(false, |png| png.quantize)
+            });
+        if quantize && !cfg!(feature = "quantize") {
+            anyhow::bail!(
+                "png.quantize needs a build with the `quantize` feature"
+            );
+        }
+        let limits = &self.limits;
+        limits.per_principal.validate("limits.per_principal")?;
+        limits.per_tenant.validate("limits.per_tenant")?;
+        Ok(())
+    }
+
+    /// The config `name` works with, its storage moved under its own prefix
+    /// and its overrides applied.
+    pub fn for_tenant(&self, name: &str, tenant: &TenantConfig) -> Self {
+        let mut config = self.clone();
+        let prefix = Path::new(TENANTS_DIR).join(name);
+        let storage = &mut config.storage;
+        storage.images_dir = storage.images_dir.join(&prefix);
+        storage.files_dir = storage.files_dir.join(&prefix);
+        let transform = &mut config.http.transform;
+        transform.cache_dir = transform.cache_dir.join(&prefix);
+        if let Some(policy) = &tenant.policy {
+            config.policy = policy.clone();
+        }
+        if let Some(encoding) = &tenant.encoding {
+            config.encoding = encoding.clone();
+        }
+        if let Some(png) = &tenant.png {
+            config.png = png.clone();
+        }
+        config.tenants.clear();
+        config
+    }
+}
+
+/// Directory holding the tenants' storage, in each storage directory.
+pub const TENANTS_DIR: &str = "tenants";
@@ -0,0 +1,367 @@
+use std::{
+	collections::BTreeMap,
+	convert::Infallible,
+	net::SocketAddr,
+	path::{Path, PathBuf},
+	sync::Arc,
+};
+
+use hyper::{
+	header::{self, HeaderValue},
+	service::{make_service_fn, service_fn},
+	Body, Method, Request, Response, Server, StatusCode,
+};
+use log::{info, warn};
+use tonic::Code;
+
+use crate::{
+	config::{Config, StorageConfig, StorageLayout, TENANTS_DIR},
+	service::{
+		error::MediaError,
+		is_valid_id,
+		layout::{parse_flat, parse_size, SIZES},
+		limit::LoadShedder,
+		meta::mime_type,
+		signing::verify_url,
+		Tenant, Tenants, WorkerPool,
+	},
+};
+
+mod negotiate;
+mod response;
+mod transform;
+
+use response::{cors, preflight, respond, status, Content, Entity};
+
+/// What the HTTP handlers share.
+#[derive(Debug)]
+pub struct State {
+	pub config: Arc<Config>,
+	pub pool: Arc<WorkerPool>,
+	pub tenants: Arc<Tenants>,
+	pub shedder: Arc<LoadShedder>,
+}
+
+/// Serves stored media on `http.addr`:
+///
+/// - `/images/<path>`, a variant as laid out on disk
+/// - `/files/<name>`, a file stored by `UploadFile`
+/// - `/media/<id>/<variant>`, a variant by id and name (`md`, `medium`, ...),
+///   rendered first when it was never generated, in the best format the
+///   `Accept` header allows
+/// - `/img/<id>?w=&h=&fit=&fmt=&q=`, a rendition of the original, see
+///   [`TransformConfig`](crate::config::TransformConfig)
+///
+/// A tenant's media is served under `/t/<tenant>`, e.g.
+/// `/t/<tenant>/media/<id>/<variant>`.
+///
+/// Private media is only served by `/media` URLs signed as
+/// [`SigningConfig`](crate::config::SigningConfig) describes, and by `/img`
+/// URLs carrying a transformation signature.
+pub async fn serve(state: Arc<State>) -> anyhow::Result<()> {
+	let addr: SocketAddr = state.config.http.addr.parse()?;
+	let config = &state.config.http.transform;
+	if config.enabled && config.cache_max_bytes > 0 {
+		let cache_dir = config.cache_dir.clone();
+		tokio::spawn(transform::prune(cache_dir, config.cache_max_bytes));
+	}
+	let make_svc = make_service_fn(move |_| {
+		let state = state.clone();
+		async move {
+			Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req)))
+		}
+	});
+	info!("Serving media on {}", addr);
+	Server::try_bind(&addr)?.serve(make_svc).await?;
+	Ok(())
+}
+
+async fn handle(
+	state: Arc<State>,
+	req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+	let mut res = match *req.method() {
+		Method::GET | Method::HEAD => route(&state, &req).await,
+		Method::OPTIONS => preflight(),
+		_ => {
+			let mut res = status(StatusCode::METHOD_NOT_ALLOWED);
+			res.headers_mut().insert(
+				header::ALLOW,
+				HeaderValue::from_static("GET, HEAD, OPTIONS"),
+			);
+			res
+		},
+	};
+	let origin = req
+		.headers()
+		.get(header::ORIGIN)
+		.and_then(|origin| origin.to_str().ok());
+	cors(&state.config.http, origin, &mut res);
+	if *req.method() == Method::HEAD {
+		// Content-Length stays, describing what GET would send
+		*res.body_mut() = Body::empty();
+	}
+	Ok(res)
+}
+
+async fn route(state: &State, req: &Request<Body>) -> Response<Body> {
+	let segments = match segments(req.uri().path()) {
+		Some(segments) => segments,
+		None => return status(StatusCode::NOT_FOUND),
+	};
+	let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+	let (tenant, segments) = match segments.as_slice() {
+		["t", name, rest @ ..] => match state.tenants.get(name) {
+			Some(tenant) => (tenant, rest),
+			None => return status(StatusCode::NOT_FOUND),
+		},
+		segments => (state.tenants.default_tenant(), segments),
+	};
+	let storage = &tenant.config.storage;
+	let served = match segments {
+		// tenants' files are only reachable under their own prefix
+		["images", TENANTS_DIR, ..] if tenant.name.is_empty() => {
+			return status(StatusCode::NOT_FOUND)
+		},
+		["images", path @ ..] if !path.is_empty() => {
+			// manifests and anything else that isn't a variant stay hidden
+			let private = match stored_id(storage, path) {
+				Some(id) => tenant.variants.is_private(&id).await,
+				None => return status(StatusCode::NOT_FOUND),
+			};
+			match private {
+				Ok(false) => {
+					serve_file(req, state, &storage.images_dir, path).await
+				},
+				Ok(true) => Err(MediaError::PermissionDenied(
+					"private media".to_string(),
+				)),
+				Err(e) => Err(e),
+			}
+		},
+		["files", name] => {
+			let served =
+				serve_file(req, state, &storage.files_dir, &[*name]).await;
+			served.map(|mut res| {
+				sandbox(&mut res);
+				res
+			})
+		},
+		["media", id, variant] if is_valid_id(id) => {
+			serve_variant(req, state, &tenant, id, variant).await
+		},
+		["img", id] if is_valid_id(id) && state.config.http.transform.enabled => {
+			match tenant.variants.is_private(id).await {
+				Ok(private) => {
+					transform::serve(req, state, &tenant, id, private).await
+				},
+				Err(e) => Err(e),
+			}
+		},
+		_ => return status(StatusCode::NOT_FOUND),
+	};
+	served.unwrap_or_else(|e| error(&e))
+}
+
+async fn serve_file(
+	req: &Request<Body>,
+	state: &State,
+	root: &Path,
+	path: &[&str],
+) -> Result<Response<Body>, MediaError> {
+	let path: PathBuf = path.iter().fold(root.to_path_buf(), |path, segment| {
+		path.join(segment)
+	});
+	let not_found = || MediaError::NotFound(path.display().to_string());
+	let file = match tokio::fs::File::open(&path).await {
+		Ok(file) => file,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+			return Err(not_found())
+		},
+		Err(e) => return Err(MediaError::storage(&path, e)),
+	};
+	let metadata = file
+		.metadata()
+		.await
+		.map_err(|e| MediaError::storage(&path, e))?;
+	if !metadata.is_file() {
+		return Err(not_found());
+	}
+	// only the range asked for is read, as it is sent
+	let len = metadata.len();
+	let last_modified = metadata.modified().ok();
+	let stamp = last_modified
+		.and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+		.map_or(0, |since| since.as_nanos());
+	let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+	Ok(respond(req, &state.config.http, Entity {
+		content_type: mime_type(ext),
+		etag: format!("\"{:x}-{:x}\"", len, stamp),
+		last_modified,
+		body: Content::File(file, len),
+	}))
+}
+
+async fn serve_variant(
+	req: &Request<Body>,
+	state: &State,
+	tenant: &Tenant,
+	id: &str,
+	variant: &str,
+) -> Result<Response<Body>, MediaError> {
+	let size = parse_size(variant)
+		.ok_or_else(|| MediaError::NotFound(format!("{}/{}", id, variant)))?;
+	let private = tenant.variants.is_private(id).await?;
+	if private {
+		let params = query(req.uri().query().unwrap_or(""));
+		let kid = params.get("kid").copied().and_then(percent_decode);
+		let signed = match (params.get("exp"), params.get("sig")) {
+			(Some(exp), Some(sig)) => verify_url(
+				&state.config.http.signing,
+				&format!("{}/media/{}/{}", tenant.url_prefix(), id, variant),
+				exp,
+				kid.as_deref(),
+				sig,
+			),
+			_ => false,
+		};
+		if !signed {
+			return Err(MediaError::PermissionDenied(
+				"missing, invalid or expired URL signature".to_string(),
+			));
+		}
+	}
+	let negotiate = &state.config.http.negotiate;
+	let stored = match negotiate::preferred(req, negotiate) {
+		Some(format) => {
+			tenant.variants.get_as(id, size, format, &state.shedder).await?
+		},
+		None => tenant.variants.get(id, size, &state.shedder).await?,
+	};
+	let mut res = respond(req, &state.config.http, Entity {
+		content_type: stored.mime_type,
+		etag: format!("\"{}\"", stored.sha256),
+		last_modified: None,
+		body: stored.buffer.into(),
+	});
+	if !negotiate.is_empty() {
+		res.headers_mut()
+			.append(header::VARY, HeaderValue::from_static("Accept"));
+	}
+	if private {
+		keep_private(&mut res);
+	}
+	Ok(res)
+}
+
+/// Keeps private media out of shared caches, browsers revalidate it with
+/// the ETag.
+fn keep_private(res: &mut Response<Body>) {
+	res.headers_mut().insert(
+		header::CACHE_CONTROL,
+		HeaderValue::from_static("private, no-cache"),
+	);
+}
+
+/// Uploaded files are whatever clients sent, an SVG or HTML file could run
+/// scripts on the origin every tenant shares. Browsers only get to display
+/// them.
+fn sandbox(res: &mut Response<Body>) {
+	let headers = res.headers_mut();
+	headers.insert(
+		header::X_CONTENT_TYPE_OPTIONS,
+		HeaderValue::from_static("nosniff"),
+	);
+	headers.insert(
+		header::CONTENT_SECURITY_POLICY,
+		HeaderValue::from_static("sandbox"),
+	);
+}
+
+/// The media a variant under `/images` belongs to, `None` for anything else.
+fn stored_id(storage: &StorageConfig, path: &[&str]) -> Option<String> {
+	match (storage.layout, path) {
+		(StorageLayout::Flat, [name]) => {
+			parse_flat(Path::new(name)).map(|(id, _, _)| id)
+		},
+		(StorageLayout::Sharded, [_, _, id, name]) => {
+			let (suffix, _) = name.rsplit_once('.')?;
+			let variant = SIZES.iter().any(|size| size.to_string() == suffix);
+			if variant {
+				Some(id.to_string())
+			} else {
+				None
+			}
+		},
+		_ => None,
+	}
+}
+
+/// The HTTP counterpart of the gRPC code `e` maps to.
+fn error(e: &MediaError) -> Response<Body> {
+	let code = match e.code() {
+		Code::NotFound => StatusCode::NOT_FOUND,
+		Code::InvalidArgument => StatusCode::BAD_REQUEST,
+		Code::PermissionDenied => StatusCode::FORBIDDEN,
+		Code::ResourceExhausted | Code::Unavailable => {
+			StatusCode::SERVICE_UNAVAILABLE
+		},
+		_ => StatusCode::INTERNAL_SERVER_ERROR,
+	};
+	if code.is_server_error() {
+		warn!("{}", e);
+	}
+	let mut res = status(code);
+	if let Some(secs) = e.retry_after_secs() {
+		res.headers_mut()
+			.insert(header::RETRY_AFTER, HeaderValue::from(secs));
+	}
+	res
+}
+
+fn query(query: &str) -> BTreeMap<&str, &str> {
+	query
+		.split('&')
+		.filter(|pair| !pair.is_empty())
+		.map(|pair| match pair.split_once('=') {
+			Some((name, value)) => (name, value),
+			None => (pair, ""),
+		})
+		.collect()
+}
+
+/// Percent-decoded path segments, `None` when one of them could step out of
+/// the served directories or name a hidden file.
+fn segments(path: &str) -> Option<Vec<String>> {
+	path.trim_start_matches('/')
+		.split('/')
+		.map(|segment| {
+			let segment = percent_decode(segment)?;
+			let safe = !segment.is_empty()
+				&& !segment.starts_with('.')
+				&& !segment.contains(|c| matches!(c, '/' | '\\' | '\0'));
+			if safe {
+				Some(segment)
+			} else {
+				None
+			}
+		})
+		.collect()
+}
+
+fn percent_decode(segment: &str) -> Option<String> {
+	let bytes = segment.as_bytes();
+	let mut decoded = Vec::with_capacity(bytes.len());
+	let mut i = 0;
+	while i < bytes.len() {
+		if bytes[i] == b'%' {
+			let hex = segment.get(i + 1..i + 3)?;
+			decoded.push(u8::from_str_radix(hex, 16).ok()?);
+			i += 3;
+		} else {
+			decoded.push(bytes[i]);
+			i += 1;
+		}
+	}
+	String::from_utf8(decoded).ok()
+}
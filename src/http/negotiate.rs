@@ -0,0 +1,35 @@
+use hyper::{header, Body, Request};
+
+use crate::{config::OutputFormat, service::meta::mime_type};
+
+/// The first of `offered` the `Accept` header explicitly lists with a
+/// non-zero quality. Wildcards don't count, browsers send `*/*` whether or
+/// not they decode AVIF.
+pub fn preferred(
+	req: &Request<Body>,
+	offered: &[OutputFormat],
+) -> Option<OutputFormat> {
+	let accept = req.headers().get(header::ACCEPT)?.to_str().ok()?;
+	let accepted: Vec<&str> = accept
+		.split(',')
+		.filter_map(|range| {
+			let mut params = range.split(';').map(str::trim);
+			let mime = params.next()?;
+			let refused = params.any(|param| {
+				param
+					.strip_prefix("q=")
+					.and_then(|q| q.parse::<f32>().ok())
+					.map_or(false, |q| q <= 0.0)
+			});
+			if refused {
+				None
+			} else {
+				Some(mime)
+			}
+		})
+		.collect();
+	offered.iter().copied().find(|format| {
+		let mime = mime_type(format.extension());
+		accepted.iter().any(|range| range.eq_ignore_ascii_case(mime))
+	})
+}
@@ -0,0 +1,296 @@
+use std::{
+	io::SeekFrom,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use hyper::{
+	header::{self, HeaderValue},
+	http::response::Builder,
+	Body, Request, Response, StatusCode,
+};
+use log::debug;
+use tokio::{
+	fs::File,
+	io::AsyncReadExt,
+};
+
+use crate::config::HttpConfig;
+
+/// Bytes read from a file at a time while sending it.
+const CHUNK: usize = 64 * 1024;
+
+/// A stored object about to be sent.
+#[derive(Debug)]
+pub struct Entity {
+	pub body: Content,
+	pub content_type: &'static str,
+	pub etag: String,
+	pub last_modified: Option<SystemTime>,
+}
+
+/// What an [`Entity`] holds.
+#[derive(Debug)]
+pub enum Content {
+	Buffer(Vec<u8>),
+	/// An open file of the given length, only the range sent is read.
+	File(File, u64),
+}
+
+impl Content {
+	fn len(&self) -> u64 {
+		match self {
+			Content::Buffer(buffer) => buffer.len() as u64,
+			Content::File(_, len) => *len,
+		}
+	}
+
+	/// `len` bytes from `start` on.
+	fn slice(self, start: u64, len: u64) -> Body {
+		match self {
+			Content::Buffer(mut buffer) => {
+				buffer.truncate((start + len) as usize);
+				buffer.drain(..start as usize);
+				buffer.into()
+			},
+			Content::File(file, _) => stream(file, start, len),
+		}
+	}
+}
+
+impl From<Vec<u8>> for Content {
+	fn from(buffer: Vec<u8>) -> Self { Content::Buffer(buffer) }
+}
+
+/// Sends `len` bytes of `file` from `start` on, as they are read.
+fn stream(mut file: File, start: u64, len: u64) -> Body {
+	let (mut sender, body) = Body::channel();
+	tokio::spawn(async move {
+		if start > 0 {
+			if let Err(e) = file.seek(SeekFrom::Start(start)).await {
+				debug!("failed to seek to {}: {}", start, e);
+				sender.abort();
+				return;
+			}
+		}
+		let mut file = file.take(len);
+		loop {
+			let mut chunk = vec![0; CHUNK];
+			let read = match file.read(&mut chunk).await {
+				Ok(0) => return,
+				Ok(read) => read,
+				Err(e) => {
+					debug!("failed to read: {}", e);
+					sender.abort();
+					return;
+				},
+			};
+			chunk.truncate(read);
+			// fails once the client is gone
+			if sender.send_data(Bytes::from(chunk)).await.is_err() {
+				return;
+			}
+		}
+	});
+	body
+}
+
+/// Part of an entity a `Range` header asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Range {
+	Full,
+	/// First and last byte, inclusive.
+	Partial(u64, u64),
+	Unsatisfiable,
+}
+
+/// Sends `entity`, answering conditional requests with `304 Not Modified`
+/// and single byte ranges with `206 Partial Content`. Multiple ranges are
+/// not supported, they get the whole entity like RFC 7233 allows.
+pub fn respond(
+	req: &Request<Body>,
+	http: &HttpConfig,
+	entity: Entity,
+) -> Response<Body> {
+	let mut builder = Response::builder()
+		.header(header::CONTENT_TYPE, entity.content_type)
+		.header(header::ETAG, entity.etag.as_str())
+		.header(header::CACHE_CONTROL, http.cache_control.as_str())
+		.header(header::ACCEPT_RANGES, "bytes");
+	if let Some(last_modified) = entity.last_modified {
+		builder = builder
+			.header(header::LAST_MODIFIED, httpdate::fmt_http_date(last_modified));
+	}
+	if is_not_modified(req, &entity) {
+		return build(builder.status(StatusCode::NOT_MODIFIED), Body::empty());
+	}
+	let len = entity.body.len();
+	match range(req, &entity) {
+		Range::Full => build(
+			builder.header(header::CONTENT_LENGTH, len),
+			entity.body.slice(0, len),
+		),
+		Range::Partial(start, end) => {
+			let body = entity.body.slice(start, end - start + 1);
+			build(
+				builder
+					.status(StatusCode::PARTIAL_CONTENT)
+					.header(
+						header::CONTENT_RANGE,
+						format!("bytes {}-{}/{}", start, end, len),
+					)
+					.header(header::CONTENT_LENGTH, end - start + 1),
+				body,
+			)
+		},
+		Range::Unsatisfiable => build(
+			builder
+				.status(StatusCode::RANGE_NOT_SATISFIABLE)
+				.header(header::CONTENT_RANGE, format!("bytes */{}", len)),
+			Body::empty(),
+		),
+	}
+}
+
+/// An empty response with `status`.
+pub fn status(status: StatusCode) -> Response<Body> {
+	build(Response::builder().status(status), Body::empty())
+}
+
+/// Answers a CORS preflight, [`cors`] adds the origin.
+pub fn preflight() -> Response<Body> {
+	build(
+		Response::builder()
+			.status(StatusCode::NO_CONTENT)
+			.header(header::ACCESS_CONTROL_ALLOW_METHODS, "GET, HEAD, OPTIONS")
+			.header(
+				header::ACCESS_CONTROL_ALLOW_HEADERS,
+				"Range, If-None-Match, If-Modified-Since, If-Range",
+			)
+			.header(header::ACCESS_CONTROL_MAX_AGE, "86400"),
+		Body::empty(),
+	)
+}
+
+/// Lets `origin` read the response when it is one of `cors_origins`.
+pub fn cors(http: &HttpConfig, origin: Option<&str>, res: &mut Response<Body>) {
+	let origin = match origin {
+		Some(origin) => origin,
+		None => return,
+	};
+	let any = http.cors_origins.iter().any(|allowed| allowed == "*");
+	let allowed = if any {
+		HeaderValue::from_static("*")
+	} else if http.cors_origins.iter().any(|allowed| allowed == origin) {
+		match HeaderValue::from_str(origin) {
+			Ok(origin) => origin,
+			Err(_) => return,
+		}
+	} else {
+		return;
+	};
+	let headers = res.headers_mut();
+	headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allowed);
+	headers.insert(
+		header::ACCESS_CONTROL_EXPOSE_HEADERS,
+		HeaderValue::from_static("Content-Length, Content-Range, ETag, Last-Modified"),
+	);
+	if !any {
+		headers.append(header::VARY, HeaderValue::from_static("Origin"));
+	}
+}
+
+/// Builders only fail on invalid header values, which would be a bug here.
+fn build(builder: Builder, body: Body) -> Response<Body> {
+	builder.body(body).unwrap_or_else(|_| {
+		let mut res = Response::new(Body::empty());
+		*res.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+		res
+	})
+}
+
+fn is_not_modified(req: &Request<Body>, entity: &Entity) -> bool {
+	let headers = req.headers();
+	if let Some(tags) = headers.get(header::IF_NONE_MATCH) {
+		// If-Modified-Since is ignored when If-None-Match is present
+		return tags.to_str().map_or(false, |tags| {
+			tags.split(',').map(str::trim).any(|tag| {
+				tag == "*" || tag.trim_start_matches("W/") == entity.etag
+			})
+		});
+	}
+	match (headers.get(header::IF_MODIFIED_SINCE), entity.last_modified) {
+		(Some(since), Some(last_modified)) => since
+			.to_str()
+			.ok()
+			.and_then(|since| httpdate::parse_http_date(since).ok())
+			.map_or(false, |since| seconds(last_modified) <= seconds(since)),
+		_ => false,
+	}
+}
+
+fn range(req: &Request<Body>, entity: &Entity) -> Range {
+	let headers = req.headers();
+	let spec = match headers.get(header::RANGE).and_then(|r| r.to_str().ok()) {
+		Some(spec) => spec,
+		None => return Range::Full,
+	};
+	// a stale If-Range gets the whole, current entity
+	if let Some(if_range) = headers.get(header::IF_RANGE) {
+		let fresh = if_range.to_str().map_or(false, |if_range| {
+			if_range == entity.etag
+				|| match (httpdate::parse_http_date(if_range), entity.last_modified)
+				{
+					(Ok(date), Some(last_modified)) => {
+						seconds(date) == seconds(last_modified)
+					},
+					_ => false,
+				}
+		});
+		if !fresh {
+			return Range::Full;
+		}
+	}
+	parse_range(spec, entity.body.len())
+}
+
+fn parse_range(spec: &str, len: u64) -> Range {
+	let spec = match spec.trim().strip_prefix("bytes=") {
+		Some(spec) if !spec.contains(',') => spec,
+		_ => return Range::Full,
+	};
+	let (start, end) = match spec.split_once('-') {
+		Some((start, end)) => (start.trim(), end.trim()),
+		None => return Range::Full,
+	};
+	if start.is_empty() {
+		// the last `end` bytes
+		return match end.parse::<u64>() {
+			Ok(0) => Range::Unsatisfiable,
+			Ok(_) if len == 0 => Range::Unsatisfiable,
+			Ok(suffix) => Range::Partial(len.saturating_sub(suffix), len - 1),
+			Err(_) => Range::Full,
+		};
+	}
+	let start = match start.parse::<u64>() {
+		Ok(start) => start,
+		Err(_) => return Range::Full,
+	};
+	let end = if end.is_empty() {
+		len.saturating_sub(1)
+	} else {
+		match end.parse::<u64>() {
+			Ok(end) if end >= start => end.min(len.saturating_sub(1)),
+			_ => return Range::Full,
+		}
+	};
+	if start >= len {
+		return Range::Unsatisfiable;
+	}
+	Range::Partial(start, end)
+}
+
+fn seconds(time: SystemTime) -> Duration {
+	let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+	Duration::from_secs(since_epoch.as_secs())
+}
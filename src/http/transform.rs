@@ -0,0 +1,322 @@
+use std::{
+	collections::BTreeMap,
+	fs, io,
+	path::{Path, PathBuf},
+	time::{Duration, SystemTime},
+};
+
+use hyper::{Body, Request, Response};
+use log::{debug, warn};
+
+use super::{
+	keep_private, percent_decode, query,
+	response::{respond, Entity},
+	State,
+};
+use crate::{
+	config::{OutputFormat, SigningConfig, TransformConfig},
+	pb::atwany::media::Size,
+	service::{
+		error::MediaError,
+		meta::{mime_type, sha256_hex},
+		signing::{transform_message, verify, verify_url},
+		storage::{commit, Pending},
+		transform::{render, Fit, Spec},
+		Tenant,
+	},
+};
+
+/// How often [`prune`] looks at the size of the cache.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Serves `/img/<id>`, rendering the requested rendition of the original the
+/// first time it is asked for and caching it in `cache_dir`. Renditions of
+/// `private` media need an expiring signature, like its `/media` URLs.
+pub async fn serve(
+	req: &Request<Body>,
+	state: &State,
+	tenant: &Tenant,
+	id: &str,
+	private: bool,
+) -> Result<Response<Body>, MediaError> {
+	let config = &tenant.config.http.transform;
+	let params = query(req.uri().query().unwrap_or(""));
+	let spec = parse(&params, config)?;
+	if private {
+		let path = format!("{}/img/{}", tenant.url_prefix(), id);
+		authorize_private(&path, &params, &tenant.config.http.signing)?;
+	} else {
+		authorize(req.uri().path(), &params, &spec, config)?;
+	}
+
+	// the original's checksum keeps a re-upload from hitting stale renditions
+	let version = tenant
+		.variants
+		.recorded_sha256(id, Size::Original)
+		.await
+		.unwrap_or_default();
+	let key = sha256_hex(
+		format!(
+			"{}|{}|{:?}|{:?}|{}|{}|{}",
+			id,
+			version,
+			spec.width,
+			spec.height,
+			spec.fit.name(),
+			spec.format.extension(),
+			spec.quality
+		)
+		.as_bytes(),
+	);
+	let ext = spec.format.extension();
+	let path = config
+		.cache_dir
+		.join(&key[..2])
+		.join(format!("{}.{}", key, ext));
+	let body = match tokio::fs::read(&path).await {
+		Ok(body) => body,
+		Err(_) => {
+			state.shedder.admit()?;
+			debug!("rendering {} as {:?}", id, spec);
+			let original = tenant
+				.variants
+				.get(id, Size::Original, &state.shedder)
+				.await?;
+			let app = tenant.config.clone();
+			// renditions are a cache, only the work is charged
+			let body = tenant
+				.usage
+				.run(&state.pool, move || render(original.buffer, &spec, &app))
+				.await??;
+			let file = Pending {
+				path,
+				buffer: body.clone(),
+			};
+			tokio::task::spawn_blocking(move || commit(vec![file]))
+				.await
+				.map_err(|_| MediaError::WorkerFailed)??;
+			body
+		},
+	};
+	let mut res = respond(req, &state.config.http, Entity {
+		content_type: mime_type(ext),
+		etag: format!("\"{}\"", key),
+		last_modified: None,
+		body: body.into(),
+	});
+	if private {
+		keep_private(&mut res);
+	}
+	Ok(res)
+}
+
+fn parse(
+	params: &BTreeMap<&str, &str>,
+	config: &TransformConfig,
+) -> Result<Spec, MediaError> {
+	let invalid = |name: &str| {
+		MediaError::InvalidArgument(format!("invalid `{}` parameter", name))
+	};
+	let dimension = |name: &str| -> Result<Option<u32>, MediaError> {
+		match params.get(name) {
+			None => Ok(None),
+			Some(value) => match value.parse::<u32>() {
+				Ok(v) if (1..=config.max_dimension).contains(&v) => Ok(Some(v)),
+				_ => Err(invalid(name)),
+			},
+		}
+	};
+	let fit = match params.get("fit") {
+		None => Fit::Contain,
+		Some(fit) => Fit::parse(fit).ok_or_else(|| invalid("fit"))?,
+	};
+	let format = match params.get("fmt").copied() {
+		None | Some("jpeg") | Some("jpg") => OutputFormat::Jpeg,
+		Some("png") => OutputFormat::Png,
+		Some("webp") => OutputFormat::Webp,
+		Some("avif") => OutputFormat::Avif,
+		Some(_) => return Err(invalid("fmt")),
+	};
+	let quality = match params.get("q") {
+		None => config.default_quality,
+		Some(q) => match q.parse::<u8>() {
+			Ok(q) if (1..=100).contains(&q) => q,
+			_ => return Err(invalid("q")),
+		},
+	};
+	Ok(Spec {
+		width: dimension("w")?,
+		height: dimension("h")?,
+		fit,
+		format,
+		quality,
+	})
+}
+
+/// A valid signature allows anything, otherwise every parameter has to be
+/// on its allow-list.
+fn authorize(
+	path: &str,
+	params: &BTreeMap<&str, &str>,
+	spec: &Spec,
+	config: &TransformConfig,
+) -> Result<(), MediaError> {
+	let denied = |msg: &str| MediaError::PermissionDenied(msg.to_string());
+	if !config.signing_key.is_empty() {
+		if let Some(signature) = params.get("sig") {
+			let signed: Vec<String> = params
+				.iter()
+				.filter(|(name, _)| **name != "sig")
+				.map(|(name, value)| format!("{}={}", name, value))
+				.collect();
+			let message = format!("{}?{}", path, signed.join("&"));
+			if verify(&config.signing_key, &message, signature) {
+				return Ok(());
+			}
+			return Err(denied("invalid signature"));
+		}
+		if config.require_signature {
+			return Err(denied("unsigned transformation"));
+		}
+	}
+	let allowed = |list: &[u32], value: Option<u32>| {
+		list.is_empty() || value.map_or(true, |value| list.contains(&value))
+	};
+	if !allowed(&config.widths, spec.width) {
+		return Err(denied("width not allowed"));
+	}
+	if !allowed(&config.heights, spec.height) {
+		return Err(denied("height not allowed"));
+	}
+	if !config.formats.is_empty() && !config.formats.contains(&spec.format) {
+		return Err(denied("format not allowed"));
+	}
+	if !config.qualities.is_empty() && !config.qualities.contains(&spec.quality)
+	{
+		return Err(denied("quality not allowed"));
+	}
+	Ok(())
+}
+
+/// Transformation signatures never expire, so private media takes the
+/// `exp`/`kid`/`sig` parameters of its `/media` URLs instead, signed with an
+/// `http.signing` key over the [`transform_message`]. `SignUrl` issues them.
+fn authorize_private(
+	path: &str,
+	params: &BTreeMap<&str, &str>,
+	signing: &SigningConfig,
+) -> Result<(), MediaError> {
+	let message = transform_message(path, params);
+	let kid = params.get("kid").copied().and_then(percent_decode);
+	let valid = match (params.get("exp"), params.get("sig")) {
+		(Some(exp), Some(sig)) => {
+			verify_url(signing, &message, exp, kid.as_deref(), sig)
+		},
+		_ => false,
+	};
+	if valid {
+		Ok(())
+	} else {
+		Err(MediaError::PermissionDenied(
+			"missing, invalid or expired URL signature".to_string(),
+		))
+	}
+}
+
+/// Keeps `cache_dir`, tenants' renditions included, under `max_bytes` by
+/// removing the oldest renditions first.
+pub async fn prune(cache_dir: PathBuf, max_bytes: u64) {
+	let mut ticker = tokio::time::interval(PRUNE_INTERVAL);
+	loop {
+		ticker.tick().await;
+		let dir = cache_dir.clone();
+		let pruned =
+			tokio::task::spawn_blocking(move || evict(&dir, max_bytes)).await;
+		match pruned {
+			Ok(Ok(0)) => {},
+			Ok(Ok(removed)) => debug!("removed {} cached renditions", removed),
+			Ok(Err(e)) => {
+				warn!("Failed to prune {}: {}", cache_dir.display(), e)
+			},
+			Err(_) => warn!("Pruning {} panicked", cache_dir.display()),
+		}
+	}
+}
+
+/// Removes renditions, oldest first, until `dir` holds at most `max_bytes`.
+fn evict(dir: &Path, max_bytes: u64) -> io::Result<usize> {
+	let mut files = Vec::new();
+	if let Err(e) = walk(dir, &mut files) {
+		// nothing was rendered yet
+		if e.kind() == io::ErrorKind::NotFound {
+			return Ok(0);
+		}
+		return Err(e);
+	}
+	let mut total: u64 = files.iter().map(|&(_, len, _)| len).sum();
+	if total <= max_bytes {
+		return Ok(0);
+	}
+	files.sort_by_key(|&(modified, _, _)| modified);
+	let mut removed = 0;
+	for (_, len, path) in files {
+		if total <= max_bytes {
+			break;
+		}
+		match fs::remove_file(&path) {
+			Ok(()) => removed += 1,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+			Err(e) => return Err(e),
+		}
+		total -= len;
+	}
+	Ok(removed)
+}
+
+/// Every file under `dir`, with when it was written and its length.
+fn walk(
+	dir: &Path,
+	files: &mut Vec<(SystemTime, u64, PathBuf)>,
+) -> io::Result<()> {
+	for entry in fs::read_dir(dir)? {
+		let entry = entry?;
+		let metadata = entry.metadata()?;
+		if metadata.is_dir() {
+			walk(&entry.path(), files)?;
+		} else if metadata.is_file() {
+			let modified = metadata.modified()?;
+			files.push((modified, metadata.len(), entry.path()));
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{config::SigningKey, service::signing::sign_transform_url};
+
+	#[test]
+	fn signed_renditions_are_authorized() {
+		let signing = SigningConfig {
+			keys: vec![SigningKey {
+				id: "2024-06".to_string(),
+				secret: "secret".to_string(),
+			}],
+			..SigningConfig::default()
+		};
+		let params: BTreeMap<&str, &str> =
+			vec![("w", "320"), ("fit", "cover"), ("fmt", "webp")]
+				.into_iter()
+				.collect();
+		let signed = sign_transform_url(&signing, "/t/acme", "abc", &params, 60)
+			.unwrap();
+		let (path, signed_query) = signed.url.split_once('?').unwrap();
+		assert_eq!(path, "/t/acme/img/abc");
+		let params = query(signed_query);
+		assert!(authorize_private(path, &params, &signing).is_ok());
+
+		let tampered = signed_query.replace("w=320", "w=1920");
+		assert!(authorize_private(path, &query(&tampered), &signing).is_err());
+	}
+}
@@ -10,28 +10,243 @@ clippy::missing_const_for_fn
 #![feature(async_closure)]
 
 use async_ctrlc::CtrlC;
-use log::info;
-use std::env;
+use futures::{channel::mpsc, StreamExt};
+use log::{error, info};
+use pb::atwany::media::{ReprocessProgress, Size};
+use service::{
+    health::{HealthServer, HealthService},
+    reflection::{ReflectionService, ServerReflectionServer},
+};
+use std::{env, net::SocketAddr, sync::Arc, time::Duration};
+use structopt::StructOpt;
 use tonic::transport::Server;
 
+mod config;
+mod http;
+mod migrate;
 mod pb;
 mod service;
+mod tls;
+
+/// How long calls in flight at shutdown get to finish over TLS.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often the tenants' usage is written to disk.
+const USAGE_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "atwany")]
+struct Opt {
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Serve the gRPC API, the default
+    Serve,
+    /// Move variants stored in the flat layout into the sharded one
+    MigrateLayout {
+        /// Only log the moves
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// Regenerate variants that are missing or were made by an older preset
+    Reprocess {
+        /// Only report what would be regenerated
+        #[structopt(long)]
+        dry_run: bool,
+        /// Pause between media, defaults to `reprocess.throttle_ms`
+        #[structopt(long)]
+        throttle_ms: Option<u64>,
+    },
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     env::set_var("RUST_LOG", "atwany");
 //    dotenv::dotenv()?;
     pretty_env_logger::init_timed();
-    let addr = "0.0.0.0:50051".parse()?;
-    info!("Starting Server on {}", addr);
-    let svc = service::MediaServer::new(service::MediaService);
-    Server::builder()
+    let config = config::Config::load()?;
+    match Opt::from_args().command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(config).await,
+        Command::MigrateLayout { dry_run } => {
+            let mut storages = vec![(String::new(), config.storage.clone())];
+            for (name, tenant) in &config.tenants {
+                let storage = config.for_tenant(name, tenant).storage;
+                // nothing was uploaded for it yet
+                if storage.images_dir.exists() {
+                    storages.push((name.clone(), storage));
+                }
+            }
+            for (tenant, storage) in storages {
+                let report = tokio::task::spawn_blocking(move || {
+                    migrate::migrate_layout(&storage, dry_run)
+                })
+                .await??;
+                info!("{:?} {:?}", tenant, report);
+            }
+            Ok(())
+        },
+        Command::Reprocess {
+            dry_run,
+            throttle_ms,
+        } => reprocess(config, dry_run, throttle_ms).await,
+    }
+}
 
+async fn reprocess(
+    config: config::Config,
+    dry_run: bool,
+    throttle_ms: Option<u64>,
+) -> anyhow::Result<()> {
+    let throttle = Duration::from_millis(
+        throttle_ms.unwrap_or(config.reprocess.throttle_ms),
+    );
+    let config = Arc::new(config);
+    let pool = Arc::new(service::WorkerPool::new(&config.pool));
+    let tenants = Arc::new(service::Tenants::new(config, pool.clone())?);
+    let (mut regenerated, mut failed) = (0, 0);
+    for tenant in tenants.all() {
+        if !tenant.name.is_empty() {
+            if !tenant.config.storage.images_dir.exists() {
+                continue;
+            }
+            info!("Reprocessing tenant {}", tenant.name);
+        }
+        let (tx, rx) = mpsc::channel(4);
+        tokio::spawn(service::reprocess::run(
+            tenant.config.clone(),
+            pool.clone(),
+            tenant.usage.clone(),
+            dry_run,
+            throttle,
+            tx,
+        ));
+        let (r, f) = report_progress(rx).await?;
+        regenerated += r;
+        failed += f;
+    }
+    info!("{} media regenerated, {} failed", regenerated, failed);
+    tokio::task::spawn_blocking(move || tenants.flush_usage()).await?;
+    Ok(())
+}
+
+/// Logs the progress of one reprocessing run, returning how many media
+/// were regenerated and how many failed.
+async fn report_progress(
+    mut rx: mpsc::Receiver<Result<ReprocessProgress, tonic::Status>>,
+) -> anyhow::Result<(usize, usize)> {
+    let (mut regenerated, mut failed) = (0, 0);
+    while let Some(progress) = rx.next().await {
+        let progress = progress?;
+        if !progress.error.is_empty() {
+            failed += 1;
+        } else if !progress.regenerated.is_empty() {
+            regenerated += 1;
+        }
+        let sizes: Vec<_> = progress
+            .regenerated
+            .iter()
+            .filter_map(|size| Size::from_i32(*size))
+            .map(|size| size.to_string())
+            .collect();
+        info!(
+            "[{}/{}] {} regenerated=[{}] {}",
+            progress.processed,
+            progress.total,
+            progress.id,
+            sizes.join(","),
+            progress.error
+        );
+    }
+    Ok((regenerated, failed))
+}
+
+async fn serve(config: config::Config) -> anyhow::Result<()> {
+    let addr: SocketAddr = config.grpc.addr.parse()?;
+    let tls = config.grpc.tls.clone();
+    info!("Starting Server on {}", addr);
+    let config = Arc::new(config);
+    let pool = Arc::new(service::WorkerPool::new(&config.pool));
+    if config.pool.metrics_interval_secs > 0 {
+        let interval = Duration::from_secs(config.pool.metrics_interval_secs);
+        tokio::spawn(pool.clone().report(interval));
+    }
+    let storage = &config.storage;
+    let dirs = [
+        storage.images_dir.clone(),
+        storage.files_dir.clone(),
+        config.http.transform.cache_dir.clone(),
+    ];
+    // before anything commits, the tenants' directories are nested in these
+    tokio::task::spawn_blocking(move || {
+        for dir in &dirs {
+            service::storage::clean(dir);
+        }
+    })
+    .await?;
+    let tenants =
+        Arc::new(service::Tenants::new(config.clone(), pool.clone())?);
+    tokio::spawn(tenants.clone().flush_usage_every(USAGE_FLUSH_INTERVAL));
+    let shedding = config.limits.shedding;
+    let shedder =
+        Arc::new(service::limit::LoadShedder::new(shedding, pool.clone()));
+    if shedding.enabled && shedding.max_memory_bytes > 0 {
+        tokio::spawn(shedder.clone().monitor());
+    }
+    if config.http.enabled {
+        let state = Arc::new(http::State {
+            config: config.clone(),
+            pool: pool.clone(),
+            tenants: tenants.clone(),
+            shedder: shedder.clone(),
+        });
+        tokio::spawn(async move {
+            if let Err(e) = http::serve(state).await {
+                error!("HTTP server failed: {}", e);
+            }
+        });
+    }
+    let (health, checker) = HealthService::new(config.clone(), pool.clone());
+    let interval = Duration::from_secs(config.grpc.health_interval_secs.max(1));
+    tokio::spawn(checker.run(interval));
+    let auth =
+        Arc::new(service::auth::Authenticator::new(config.auth.clone())?);
+    let authenticate = auth.clone().interceptor();
+    let limiter =
+        service::limit::RateLimiter::new(config.limits, tenants.clone());
+    let limit = Arc::new(limiter).interceptor();
+    let svc = service::MediaServer::with_interceptor(
+        service::MediaService::new(tenants.clone(), pool, shedder),
+        // the rate limits are per principal, known once authenticated
+        move |request| limit(authenticate(request)?),
+    );
+    // describes the API, so only to the clients allowed to call it
+    let reflection = ServerReflectionServer::with_interceptor(
+        ReflectionService::new(config.grpc.reflection)?,
+        auth.interceptor(),
+    );
+    // load balancers probe without credentials
+    let health = HealthServer::new(health);
+    let router = Server::builder()
         .concurrency_limit_per_connection(100)
         .tcp_nodelay(true)
         .add_service(svc)
-        .serve_with_shutdown(addr, CtrlC::new()?)
-        .await?;
+        .add_service(health)
+        .add_service(reflection);
+    let ctrl_c = CtrlC::new()?;
+    if tls.enabled {
+        info!("Serving gRPC over TLS");
+        let (incoming, connections) = tls::incoming(addr, &tls, ctrl_c).await?;
+        // returns once Ctrl-C ends the incoming stream, the connections keep
+        // serving their calls
+        router.serve_with_incoming(incoming).await?;
+        connections.drain(DRAIN_TIMEOUT).await;
+    } else {
+        router.serve_with_shutdown(addr, ctrl_c).await?;
+    }
+    // what was recorded since the last periodic flush
+    tokio::task::spawn_blocking(move || tenants.flush_usage()).await?;
     info!("Shutdown ..");
     Ok(())
 }
@@ -0,0 +1,72 @@
+use log::{info, warn};
+use std::fs;
+
+use crate::{
+    config::{StorageConfig, StorageLayout},
+    service::layout::{
+        parse_flat, parse_flat_manifest, sharded_manifest_path, sharded_path,
+    },
+};
+
+/// Outcome of a [`migrate_layout`] run.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MigrationReport {
+    pub moved: usize,
+    /// The sharded path is already taken, the flat file was left alone.
+    pub conflicts: usize,
+    /// Not a variant name, or the move failed.
+    pub ignored: usize,
+}
+
+/// Moves every variant stored in the flat layout under `images_dir` into the
+/// sharded layout. Moved files leave the flat directory, so an interrupted
+/// run is picked up by running it again. The config has to be switched to
+/// the sharded layout first, a server still on the flat one would lose
+/// track of every moved file. A dry run only logs the moves it would make.
+pub fn migrate_layout(
+    config: &StorageConfig,
+    dry_run: bool,
+) -> anyhow::Result<MigrationReport> {
+    if config.layout != StorageLayout::Sharded {
+        anyhow::bail!("set `storage.layout = \"sharded\"` before migrating");
+    }
+    let mut report = MigrationReport::default();
+    for entry in fs::read_dir(&config.images_dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let source = entry.path();
+        let target = if let Some((id, size, ext)) = parse_flat(&source) {
+            sharded_path(config, &id, size, &ext)
+        } else if let Some(id) = parse_flat_manifest(&source) {
+            sharded_manifest_path(config, &id)
+        } else {
+            report.ignored += 1;
+            continue;
+        };
+        if target.exists() {
+            let (target, source) = (target.display(), source.display());
+            warn!("{} already exists, keeping {}", target, source);
+            report.conflicts += 1;
+            continue;
+        }
+        info!("{} -> {}", source.display(), target.display());
+        if dry_run {
+            report.moved += 1;
+            continue;
+        }
+        let moved = target
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::rename(&source, &target));
+        match moved {
+            Ok(()) => report.moved += 1,
+            Err(e) => {
+                warn!("failed to move {}: {}", source.display(), e);
+                report.ignored += 1;
+            },
+        }
+    }
+    Ok(report)
+}
@@ -11,6 +11,17 @@ pub mod media {
         pub media_meta: ::std::vec::Vec<upload_and_write_response::MediaSize>,
         #[prost(string, tag = "8")]
         pub blur_hash: std::string::String,
+        #[prost(bytes, tag = "9")]
+        pub thumb_hash: std::vec::Vec<u8>,
+        /// set when UploadRequest.inlinePlaceholder is true
+        #[prost(string, tag = "10")]
+        pub placeholder_data_uri: std::string::String,
+        /// SQIP style SVG, set when enabled in config or requested
+        #[prost(string, tag = "11")]
+        pub svg_placeholder: std::string::String,
+        /// unix time the signed URLs of private media expire at
+        #[prost(uint64, tag = "12")]
+        pub urls_expire_at: u64,
     }
     pub mod upload_and_write_response {
         #[derive(Clone, PartialEq, ::prost::Message)]
@@ -23,6 +34,29 @@ pub mod media {
             pub size: i32,
             #[prost(string, tag = "6")]
             pub url_suffix: std::string::String,
+            /// encoder quality, 0 when stored as uploaded
+            #[prost(uint32, tag = "7")]
+            pub quality: u32,
+            /// SSIM reached, only set by adaptive encoding
+            #[prost(double, tag = "8")]
+            pub score: f64,
+            /// bytes before PNG optimization, 0 if untouched
+            #[prost(uint64, tag = "9")]
+            pub unoptimized_size: u64,
+            /// size of the stored variant
+            #[prost(uint64, tag = "10")]
+            pub byte_length: u64,
+            /// hex encoded checksum of the stored variant
+            #[prost(string, tag = "11")]
+            pub sha256: std::string::String,
+            #[prost(string, tag = "12")]
+            pub mime_type: std::string::String,
+            /// jpeg, png, or the upload's format for passthrough originals
+            #[prost(string, tag = "13")]
+            pub format: std::string::String,
+            /// expiring URL, set for private media
+            #[prost(string, tag = "14")]
+            pub signed_url: std::string::String,
         }
     }
     #[derive(Clone, PartialEq, ::prost::Message)]
@@ -41,6 +75,26 @@ pub mod media {
         pub height: u32,
         #[prost(string, tag = "7")]
         pub url_suffix: std::string::String,
+        /// encoder quality, 0 when stored as uploaded
+        #[prost(uint32, tag = "8")]
+        pub quality: u32,
+        /// SSIM reached, only set by adaptive encoding
+        #[prost(double, tag = "9")]
+        pub score: f64,
+        /// bytes before PNG optimization, 0 if untouched
+        #[prost(uint64, tag = "10")]
+        pub unoptimized_size: u64,
+        /// size of buffer
+        #[prost(uint64, tag = "11")]
+        pub byte_length: u64,
+        /// hex encoded checksum of buffer
+        #[prost(string, tag = "12")]
+        pub sha256: std::string::String,
+        #[prost(string, tag = "13")]
+        pub mime_type: std::string::String,
+        /// jpeg, png, or the upload's format for passthrough originals
+        #[prost(string, tag = "14")]
+        pub format: std::string::String,
     }
     #[derive(Clone, PartialEq, ::prost::Message)]
     pub struct UploadRequest {
@@ -50,6 +104,15 @@ pub mod media {
         pub mimetype: i32,
         #[prost(string, tag = "3")]
         pub file_name: std::string::String,
+        /// return the placeholder as a data URI
+        #[prost(bool, tag = "4")]
+        pub inline_placeholder: bool,
+        /// generate the SVG placeholder
+        #[prost(bool, tag = "5")]
+        pub svg_placeholder: bool,
+        /// only served over HTTP through signed URLs
+        #[prost(bool, tag = "6")]
+        pub private: bool,
     }
     #[derive(Clone, PartialEq, ::prost::Message)]
     pub struct FileUpload {
@@ -64,6 +127,182 @@ pub mod media {
     pub struct FileUploadResponse {
         #[prost(string, tag = "1")]
         pub file_extension: std::string::String,
+        /// bytes received
+        #[prost(uint64, tag = "2")]
+        pub original_size: u64,
+        /// bytes written, smaller when PNG optimization applied
+        #[prost(uint64, tag = "3")]
+        pub stored_size: u64,
+        /// hex encoded checksum of the stored file
+        #[prost(string, tag = "4")]
+        pub sha256: std::string::String,
+        #[prost(string, tag = "5")]
+        pub mime_type: std::string::String,
+    }
+    /// sent in the grpc-status-details-bin trailer of failed calls
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct ErrorDetail {
+        /// stable machine readable reason, e.g. QUEUE_FULL
+        #[prost(string, tag = "1")]
+        pub reason: std::string::String,
+        #[prost(string, tag = "2")]
+        pub domain: std::string::String,
+        #[prost(map = "string, string", tag = "3")]
+        pub metadata: ::std::collections::HashMap<
+            std::string::String,
+            std::string::String,
+        >,
+    }
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct DecodePlaceholderRequest {
+        /// 0 derives it from the height or the hash
+        #[prost(uint32, tag = "3")]
+        pub width: u32,
+        #[prost(uint32, tag = "4")]
+        pub height: u32,
+        /// PNG or WEBP
+        #[prost(enumeration = "MimeType", tag = "5")]
+        pub format: i32,
+        /// return a base64 data URI instead of raw bytes
+        #[prost(bool, tag = "6")]
+        pub data_uri: bool,
+        #[prost(oneof = "decode_placeholder_request::Hash", tags = "1, 2")]
+        pub hash: ::std::option::Option<decode_placeholder_request::Hash>,
+    }
+    pub mod decode_placeholder_request {
+        #[derive(Clone, PartialEq, ::prost::Oneof)]
+        pub enum Hash {
+            #[prost(string, tag = "1")]
+            BlurHash(std::string::String),
+            #[prost(bytes, tag = "2")]
+            ThumbHash(std::vec::Vec<u8>),
+        }
+    }
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct DecodePlaceholderResponse {
+        #[prost(bytes, tag = "1")]
+        pub image: std::vec::Vec<u8>,
+        #[prost(string, tag = "2")]
+        pub data_uri: std::string::String,
+        #[prost(uint32, tag = "3")]
+        pub width: u32,
+        #[prost(uint32, tag = "4")]
+        pub height: u32,
+        #[prost(enumeration = "MimeType", tag = "5")]
+        pub mimetype: i32,
+    }
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct ReprocessRequest {
+        /// only report what is missing or outdated
+        #[prost(bool, tag = "1")]
+        pub dry_run: bool,
+        /// pause between media, 0 uses the configured one
+        #[prost(uint64, tag = "2")]
+        pub throttle_ms: u64,
+    }
+    /// one per stored media
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct ReprocessProgress {
+        #[prost(string, tag = "1")]
+        pub id: std::string::String,
+        #[prost(uint64, tag = "2")]
+        pub processed: u64,
+        #[prost(uint64, tag = "3")]
+        pub total: u64,
+        /// variants that were (or would be) rewritten
+        #[prost(enumeration = "Size", repeated, tag = "4")]
+        pub regenerated: ::std::vec::Vec<i32>,
+        /// set when this media could not be reprocessed
+        #[prost(string, tag = "5")]
+        pub error: std::string::String,
+    }
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct GetRequest {
+        /// the fileName it was uploaded with
+        #[prost(string, tag = "1")]
+        pub id: std::string::String,
+        #[prost(enumeration = "Size", tag = "2")]
+        pub size: i32,
+    }
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct GetResponse {
+        #[prost(bytes, tag = "1")]
+        pub buffer: std::vec::Vec<u8>,
+        #[prost(string, tag = "2")]
+        pub file_extension: std::string::String,
+        #[prost(string, tag = "3")]
+        pub mime_type: std::string::String,
+        #[prost(uint64, tag = "4")]
+        pub byte_length: u64,
+        #[prost(string, tag = "5")]
+        pub sha256: std::string::String,
+        /// rendered by this request, see lazy generation
+        #[prost(bool, tag = "6")]
+        pub generated: bool,
+    }
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct SignUrlRequest {
+        #[prost(string, tag = "1")]
+        pub id: std::string::String,
+        #[prost(enumeration = "Size", tag = "2")]
+        pub size: i32,
+        /// 0 uses the configured one
+        #[prost(uint64, tag = "3")]
+        pub ttl_secs: u64,
+        /// /img parameters (w, h, fit, fmt, q), signs a URL for that rendition
+        /// instead of the size variant
+        #[prost(map = "string, string", tag = "4")]
+        pub transform: ::std::collections::HashMap<
+            std::string::String,
+            std::string::String,
+        >,
+    }
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct SignUrlResponse {
+        #[prost(string, tag = "1")]
+        pub url: std::string::String,
+        /// unix time
+        #[prost(uint64, tag = "2")]
+        pub expires_at: u64,
+    }
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct GetUsageRequest {
+        /// every tenant, for callers not bound to one
+        #[prost(bool, tag = "1")]
+        pub all_tenants: bool,
+    }
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct TenantUsage {
+        /// empty for the default tenant
+        #[prost(string, tag = "1")]
+        pub tenant: std::string::String,
+        #[prost(uint64, tag = "2")]
+        pub bytes: u64,
+        #[prost(uint64, tag = "3")]
+        pub objects: u64,
+        #[prost(double, tag = "4")]
+        pub cpu_seconds: f64,
+        /// quota limits, 0 is unlimited
+        #[prost(uint64, tag = "5")]
+        pub soft_bytes: u64,
+        #[prost(uint64, tag = "6")]
+        pub hard_bytes: u64,
+        #[prost(uint64, tag = "7")]
+        pub soft_objects: u64,
+        #[prost(uint64, tag = "8")]
+        pub hard_objects: u64,
+        #[prost(uint64, tag = "9")]
+        pub soft_cpu_seconds: u64,
+        #[prost(uint64, tag = "10")]
+        pub hard_cpu_seconds: u64,
+        /// bytes, objects or cpu_seconds
+        #[prost(string, repeated, tag = "11")]
+        pub over_soft_limit: ::std::vec::Vec<std::string::String>,
+    }
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct GetUsageResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub tenants: ::std::vec::Vec<TenantUsage>,
     }
     #[derive(
         Clone,
@@ -155,9 +394,47 @@ pub mod media_server {
             tonic::Response<super::media::UploadAndWriteResponse>,
             tonic::Status,
         >;
+        async fn decode_placeholder(
+            &self,
+            request: tonic::Request<super::media::DecodePlaceholderRequest>,
+        ) -> Result<
+            tonic::Response<super::media::DecodePlaceholderResponse>,
+            tonic::Status,
+        >;
+        /// Server streaming response type for the Reprocess method.
+        type ReprocessStream: Stream<
+                Item = Result<super::media::ReprocessProgress, tonic::Status>,
+            > + Send
+            + Sync
+            + 'static;
+        /// regenerates variants whose preset changed since they were written,
+        /// for
+        /// callers not bound to a tenant
+        async fn reprocess(
+            &self,
+            request: tonic::Request<super::media::ReprocessRequest>,
+        ) -> Result<tonic::Response<Self::ReprocessStream>, tonic::Status>;
+        /// reads a stored variant, rendering it first if it was never generated
+        async fn get(
+            &self,
+            request: tonic::Request<super::media::GetRequest>,
+        ) -> Result<tonic::Response<super::media::GetResponse>, tonic::Status>;
+        /// issues a fresh expiring URL for a variant
+        async fn sign_url(
+            &self,
+            request: tonic::Request<super::media::SignUrlRequest>,
+        ) -> Result<tonic::Response<super::media::SignUrlResponse>, tonic::Status>;
+        /// storage and processing used by the caller's tenant, against its
+        /// quota
+        async fn get_usage(
+            &self,
+            request: tonic::Request<super::media::GetUsageRequest>,
+        ) -> Result<
+            tonic::Response<super::media::GetUsageResponse>,
+            tonic::Status,
+        >;
     }
     #[derive(Debug)]
-    #[doc(hidden)]
     pub struct MediaServer<T: Media> {
         inner: _Inner<T>,
     }
@@ -178,7 +455,12 @@ pub mod media_server {
             Self { inner }
         }
     }
-    impl<T: Media> Service<http::Request<HyperBody>> for MediaServer<T> {
+    impl<T, B> Service<http::Request<B>> for MediaServer<T>
+    where
+        T: Media,
+        B: HttpBody + Send + Sync + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+    {
         type Error = Never;
         type Future = BoxFuture<Self::Response, Self::Error>;
         type Response = http::Response<tonic::body::BoxBody>;
@@ -190,10 +472,11 @@ pub mod media_server {
             Poll::Ready(Ok(()))
         }
 
-        fn call(&mut self, req: http::Request<HyperBody>) -> Self::Future {
+        fn call(&mut self, req: http::Request<B>) -> Self::Future {
             let inner = self.inner.clone();
             match req.uri().path() {
                 "/atwany.Media/Upload" => {
+                    #[allow(non_camel_case_types)]
                     struct UploadSvc<T: Media>(pub Arc<T>);
                     impl<T: Media>
                         tonic::server::ServerStreamingService<
@@ -215,7 +498,7 @@ pub mod media_server {
                         ) -> Self::Future {
                             let inner = self.0.clone();
                             let fut =
-                                async move { inner.upload(request).await };
+                                async move { (*inner).upload(request).await };
                             Box::pin(fut)
                         }
                     }
@@ -239,6 +522,7 @@ pub mod media_server {
                     Box::pin(fut)
                 },
                 "/atwany.Media/UploadFile" => {
+                    #[allow(non_camel_case_types)]
                     struct UploadFileSvc<T: Media>(pub Arc<T>);
                     impl<T: Media>
                         tonic::server::UnaryService<super::media::FileUpload>
@@ -255,8 +539,9 @@ pub mod media_server {
                             request: tonic::Request<super::media::FileUpload>,
                         ) -> Self::Future {
                             let inner = self.0.clone();
-                            let fut =
-                                async move { inner.upload_file(request).await };
+                            let fut = async move {
+                                (*inner).upload_file(request).await
+                            };
                             Box::pin(fut)
                         }
                     }
@@ -280,6 +565,7 @@ pub mod media_server {
                     Box::pin(fut)
                 },
                 "/atwany.Media/UploadAndWrite" => {
+                    #[allow(non_camel_case_types)]
                     struct UploadAndWriteSvc<T: Media>(pub Arc<T>);
                     impl<T: Media>
                         tonic::server::UnaryService<super::media::UploadRequest>
@@ -299,7 +585,7 @@ pub mod media_server {
                         ) -> Self::Future {
                             let inner = self.0.clone();
                             let fut = async move {
-                                inner.upload_and_write(request).await
+                                (*inner).upload_and_write(request).await
                             };
                             Box::pin(fut)
                         }
@@ -323,6 +609,232 @@ pub mod media_server {
                     };
                     Box::pin(fut)
                 },
+                "/atwany.Media/DecodePlaceholder" => {
+                    #[allow(non_camel_case_types)]
+                    struct DecodePlaceholderSvc<T: Media>(pub Arc<T>);
+                    impl<T: Media>
+                        tonic::server::UnaryService<
+                            super::media::DecodePlaceholderRequest,
+                        > for DecodePlaceholderSvc<T>
+                    {
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        type Response = super::media::DecodePlaceholderResponse;
+
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<
+                                super::media::DecodePlaceholderRequest,
+                            >,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move {
+                                (*inner).decode_placeholder(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let interceptor = inner.1.clone();
+                        let inner = inner.0;
+                        let method = DecodePlaceholderSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = if let Some(interceptor) = interceptor {
+                            tonic::server::Grpc::with_interceptor(
+                                codec,
+                                interceptor,
+                            )
+                        } else {
+                            tonic::server::Grpc::new(codec)
+                        };
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                },
+                "/atwany.Media/Reprocess" => {
+                    #[allow(non_camel_case_types)]
+                    struct ReprocessSvc<T: Media>(pub Arc<T>);
+                    impl<T: Media>
+                        tonic::server::ServerStreamingService<
+                            super::media::ReprocessRequest,
+                        > for ReprocessSvc<T>
+                    {
+                        type Future = BoxFuture<
+                            tonic::Response<Self::ResponseStream>,
+                            tonic::Status,
+                        >;
+                        type Response = super::media::ReprocessProgress;
+                        type ResponseStream = T::ReprocessStream;
+
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<
+                                super::media::ReprocessRequest,
+                            >,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move {
+                                (*inner).reprocess(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let interceptor = inner.1;
+                        let inner = inner.0;
+                        let method = ReprocessSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = if let Some(interceptor) = interceptor {
+                            tonic::server::Grpc::with_interceptor(
+                                codec,
+                                interceptor,
+                            )
+                        } else {
+                            tonic::server::Grpc::new(codec)
+                        };
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                },
+                "/atwany.Media/Get" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetSvc<T: Media>(pub Arc<T>);
+                    impl<T: Media>
+                        tonic::server::UnaryService<super::media::GetRequest>
+                        for GetSvc<T>
+                    {
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        type Response = super::media::GetResponse;
+
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::media::GetRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut =
+                                async move { (*inner).get(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let interceptor = inner.1.clone();
+                        let inner = inner.0;
+                        let method = GetSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = if let Some(interceptor) = interceptor {
+                            tonic::server::Grpc::with_interceptor(
+                                codec,
+                                interceptor,
+                            )
+                        } else {
+                            tonic::server::Grpc::new(codec)
+                        };
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                },
+                "/atwany.Media/SignUrl" => {
+                    #[allow(non_camel_case_types)]
+                    struct SignUrlSvc<T: Media>(pub Arc<T>);
+                    impl<T: Media>
+                        tonic::server::UnaryService<
+                            super::media::SignUrlRequest,
+                        > for SignUrlSvc<T>
+                    {
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        type Response = super::media::SignUrlResponse;
+
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<
+                                super::media::SignUrlRequest,
+                            >,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut =
+                                async move { (*inner).sign_url(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let interceptor = inner.1.clone();
+                        let inner = inner.0;
+                        let method = SignUrlSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = if let Some(interceptor) = interceptor {
+                            tonic::server::Grpc::with_interceptor(
+                                codec,
+                                interceptor,
+                            )
+                        } else {
+                            tonic::server::Grpc::new(codec)
+                        };
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                },
+                "/atwany.Media/GetUsage" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetUsageSvc<T: Media>(pub Arc<T>);
+                    impl<T: Media>
+                        tonic::server::UnaryService<
+                            super::media::GetUsageRequest,
+                        > for GetUsageSvc<T>
+                    {
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        type Response = super::media::GetUsageResponse;
+
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<
+                                super::media::GetUsageRequest,
+                            >,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move {
+                                (*inner).get_usage(request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let interceptor = inner.1.clone();
+                        let inner = inner.0;
+                        let method = GetUsageSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = if let Some(interceptor) = interceptor {
+                            tonic::server::Grpc::with_interceptor(
+                                codec,
+                                interceptor,
+                            )
+                        } else {
+                            tonic::server::Grpc::new(codec)
+                        };
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                },
                 _ => Box::pin(async move {
                     Ok(http::Response::builder()
                         .status(200)
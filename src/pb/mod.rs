@@ -1 +1,13 @@
 pub mod atwany;
+/// `grpc.health.v1`
+#[path = "grpc.health.v1.rs"]
+pub mod health;
+/// `grpc.reflection.v1alpha`
+#[path = "grpc.reflection.v1alpha.rs"]
+#[allow(clippy::enum_variant_names)]
+pub mod reflection;
+
+/// Every proto file the server implements as a serialized
+/// `FileDescriptorSet`, for server reflection.
+pub const FILE_DESCRIPTOR_SET: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/atwany_descriptor.bin"));
@@ -0,0 +1,257 @@
+use std::{fmt, fs, sync::Arc};
+
+use anyhow::Context;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tonic::{
+	metadata::{MetadataMap, MetadataValue},
+	Request, Status,
+};
+
+use super::error::MediaError;
+use crate::config::AuthConfig;
+
+/// Where the interceptor leaves the [`Principal`] for the handlers. Whatever
+/// a client sends under it is replaced.
+const PRINCIPAL: &str = "x-atwany-principal-bin";
+const API_KEY: &str = "x-api-key";
+const AUTHORIZATION: &str = "authorization";
+
+/// How a caller proved who it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Credential {
+	/// Authentication is disabled.
+	Anonymous,
+	ApiKey,
+	Jwt,
+}
+
+/// The caller of a gRPC method.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Principal {
+	/// Name of the API key or `sub` of the JWT.
+	pub name: String,
+	pub credential: Credential,
+	/// The tenant its credential is bound to.
+	pub tenant: Option<String>,
+}
+
+impl Principal {
+	const fn anonymous() -> Self {
+		Self {
+			name: String::new(),
+			credential: Credential::Anonymous,
+			tenant: None,
+		}
+	}
+
+	/// The principal [`Authenticator::interceptor`] attached to `request`.
+	pub fn of<T>(request: &Request<T>) -> Self {
+		request
+			.metadata()
+			.get_bin(PRINCIPAL)
+			.and_then(|value| value.to_bytes().ok())
+			.and_then(|raw| serde_json::from_slice(&raw).ok())
+			.unwrap_or_else(Self::anonymous)
+	}
+}
+
+impl fmt::Display for Principal {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self.credential {
+			Credential::Anonymous => write!(f, "anonymous"),
+			Credential::ApiKey => write!(f, "api-key:{}", self.name),
+			Credential::Jwt => write!(f, "jwt:{}", self.name),
+		}
+	}
+}
+
+#[derive(Debug, Deserialize)]
+struct Jwks {
+	keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Jwk {
+	kty: String,
+	kid: Option<String>,
+	n: Option<String>,
+	e: Option<String>,
+}
+
+/// Public part of an RS256 key, base64url encoded like in the JWKS.
+#[derive(Debug)]
+struct RsaKey {
+	kid: Option<String>,
+	modulus: String,
+	exponent: String,
+}
+
+type Claims = serde_json::Map<String, serde_json::Value>;
+
+/// Checks the credentials of every gRPC call, see
+/// [`AuthConfig`](crate::config::AuthConfig).
+#[derive(Debug)]
+pub struct Authenticator {
+	config: AuthConfig,
+	rsa_keys: Vec<RsaKey>,
+}
+
+impl Authenticator {
+	/// Reads the JWKS file, failing when authentication is enabled without
+	/// a way to pass it, and warns loudly when it is disabled.
+	pub fn new(config: AuthConfig) -> anyhow::Result<Self> {
+		let rsa_keys = match &config.jwt.jwks_file {
+			Some(path) => {
+				let raw = fs::read(path)
+					.with_context(|| format!("reading {}", path.display()))?;
+				let jwks: Jwks = serde_json::from_slice(&raw)
+					.with_context(|| format!("parsing {}", path.display()))?;
+				jwks.keys
+					.into_iter()
+					.filter(|jwk| jwk.kty == "RSA")
+					.filter_map(|jwk| {
+						Some(RsaKey {
+							kid: jwk.kid,
+							modulus: jwk.n?,
+							exponent: jwk.e?,
+						})
+					})
+					.collect()
+			},
+			None => Vec::new(),
+		};
+		let no_credentials = config.api_keys.is_empty()
+			&& config.jwt.secret.is_empty()
+			&& rsa_keys.is_empty();
+		if config.enabled && no_credentials {
+			anyhow::bail!("auth is enabled without API keys or JWT keys");
+		}
+		if !config.enabled {
+			warn!(
+				"Authentication is DISABLED, any client reaching the gRPC port \
+				 can upload, overwrite and read media. Set auth.enabled with \
+				 API keys or JWT keys before exposing it."
+			);
+		}
+		Ok(Self { config, rsa_keys })
+	}
+
+	/// Authenticates a call for the generated server's `with_interceptor`,
+	/// passing the principal on in the request metadata.
+	pub fn interceptor(
+		self: Arc<Self>,
+	) -> impl Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync + 'static {
+		move |mut request| {
+			let principal = self.authenticate(request.metadata())?;
+			// a struct of strings always serializes
+			let raw = serde_json::to_vec(&principal).unwrap_or_default();
+			request
+				.metadata_mut()
+				.insert_bin(PRINCIPAL, MetadataValue::from_bytes(&raw));
+			Ok(request)
+		}
+	}
+
+	fn authenticate(
+		&self,
+		metadata: &MetadataMap,
+	) -> Result<Principal, MediaError> {
+		if !self.config.enabled {
+			return Ok(Principal::anonymous());
+		}
+		let unauthenticated =
+			|msg: &str| MediaError::Unauthenticated(msg.to_string());
+		if let Some(key) = metadata.get(API_KEY) {
+			let key = key
+				.to_str()
+				.map_err(|_| unauthenticated("malformed API key"))?;
+			return self
+				.api_key(key)
+				.ok_or_else(|| unauthenticated("unknown API key"));
+		}
+		if let Some(authorization) = metadata.get(AUTHORIZATION) {
+			let token = authorization
+				.to_str()
+				.ok()
+				.and_then(|value| value.strip_prefix("Bearer "))
+				.ok_or_else(|| unauthenticated("expected a bearer token"))?;
+			return self.jwt(token.trim());
+		}
+		Err(unauthenticated("missing credentials"))
+	}
+
+	fn api_key(&self, key: &str) -> Option<Principal> {
+		// comparing digests keeps the timing independent of the keys
+		let digest = Sha256::digest(key.as_bytes());
+		self.config
+			.api_keys
+			.iter()
+			.find(|known| Sha256::digest(known.key.as_bytes()) == digest)
+			.map(|known| Principal {
+				name: known.name.clone(),
+				credential: Credential::ApiKey,
+				tenant: known.tenant.clone(),
+			})
+	}
+
+	fn jwt(&self, token: &str) -> Result<Principal, MediaError> {
+		let invalid = |e: jsonwebtoken::errors::Error| {
+			MediaError::Unauthenticated(format!("invalid token: {}", e))
+		};
+		let header = decode_header(token).map_err(invalid)?;
+		let jwt = &self.config.jwt;
+		// only the algorithm of the key it is checked against, so an RSA
+		// public key never doubles as an HMAC secret
+		let mut validation = Validation::new(header.alg);
+		validation.leeway = jwt.leeway_secs;
+		validation.iss.clone_from(&jwt.issuer);
+		if !jwt.audience.is_empty() {
+			validation.set_audience(&jwt.audience);
+		}
+		let claims = match header.alg {
+			Algorithm::HS256 if !jwt.secret.is_empty() => {
+				let key = DecodingKey::from_secret(jwt.secret.as_bytes());
+				decode::<Claims>(token, &key, &validation)
+					.map_err(invalid)?
+					.claims
+			},
+			Algorithm::RS256 => {
+				self.rsa_keys
+					.iter()
+					.filter(|key| header.kid.is_none() || key.kid == header.kid)
+					.find_map(|key| {
+						let key = DecodingKey::from_rsa_components(
+							&key.modulus,
+							&key.exponent,
+						);
+						decode::<Claims>(token, &key, &validation).ok()
+					})
+					.ok_or_else(|| {
+						MediaError::Unauthenticated("invalid token".to_string())
+					})?
+					.claims
+			},
+			alg => {
+				return Err(MediaError::Unauthenticated(format!(
+					"{:?} tokens are not accepted",
+					alg
+				)))
+			},
+		};
+		let claim = |name: &str| {
+			claims.get(name).and_then(|value| value.as_str()).map(str::to_string)
+		};
+		let name = claim("sub").ok_or_else(|| {
+			MediaError::Unauthenticated("token has no subject".to_string())
+		})?;
+		Ok(Principal {
+			name,
+			credential: Credential::Jwt,
+			tenant: claim(&jwt.tenant_claim),
+		})
+	}
+}
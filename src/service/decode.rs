@@ -0,0 +1,88 @@
+use image::{
+	DynamicImage, GenericImageView, ImageBuffer, ImageFormat, ImageResult,
+};
+use jpeg_decoder::PixelFormat;
+
+use super::resize::fit;
+
+/// An uploaded image ready to be resized.
+#[derive(Debug)]
+pub struct Source {
+	/// Decoded pixels, possibly already scaled down from the upload.
+	pub image: DynamicImage,
+	/// Dimensions of the upload itself.
+	pub width: u32,
+	pub height: u32,
+	pub format: ImageFormat,
+	/// The upload, kept when the original is stored as-is rather than
+	/// re-encoded.
+	pub original: Option<Vec<u8>>,
+}
+
+impl Source {
+	/// Extension the original is stored under, JPEGs keep the `jpeg` the
+	/// re-encoded variants use.
+	pub fn extension(&self) -> &'static str {
+		match self.format {
+			ImageFormat::Jpeg => "jpeg",
+			format => format.extensions_str().first().copied().unwrap_or("bin"),
+		}
+	}
+}
+
+/// Decodes an upload. When `max_dim` is set the caller only needs pixels for
+/// variants up to that size, so JPEGs are decoded with a scaled IDCT (1/2,
+/// 1/4 or 1/8) straight to the smallest scale that still covers it instead
+/// of materializing every pixel.
+pub fn decode(buffer: Vec<u8>, max_dim: Option<u32>) -> ImageResult<Source> {
+	let format = image::guess_format(&buffer)?;
+	let scaled = match (format, max_dim) {
+		(ImageFormat::Jpeg, Some(dim)) => decode_jpeg_scaled(&buffer, dim),
+		_ => None,
+	};
+	let (image, width, height) = match scaled {
+		Some(scaled) => scaled,
+		None => {
+			let image = image::load_from_memory_with_format(&buffer, format)?;
+			let (width, height) = image.dimensions();
+			(image, width, height)
+		},
+	};
+	Ok(Source {
+		image,
+		width,
+		height,
+		format,
+		original: max_dim.map(|_| buffer),
+	})
+}
+
+/// `None` leaves it to the regular decoder, which also deals with CMYK.
+fn decode_jpeg_scaled(
+	buffer: &[u8],
+	dim: u32,
+) -> Option<(DynamicImage, u32, u32)> {
+	let mut decoder = jpeg_decoder::Decoder::new(buffer);
+	decoder.read_info().ok()?;
+	let info = decoder.info()?;
+	let (width, height) = (u32::from(info.width), u32::from(info.height));
+	if width <= dim && height <= dim {
+		return None;
+	}
+	let (target_width, target_height) = fit((width, height), dim);
+	decoder.scale(target_width as u16, target_height as u16).ok()?;
+	let pixels = decoder.decode().ok()?;
+	let info = decoder.info()?;
+	let (w, h) = (u32::from(info.width), u32::from(info.height));
+	let image = match info.pixel_format {
+		PixelFormat::L8 => {
+			ImageBuffer::from_raw(w, h, pixels).map(DynamicImage::ImageLuma8)
+		},
+		PixelFormat::RGB24 => {
+			ImageBuffer::from_raw(w, h, pixels).map(DynamicImage::ImageRgb8)
+		},
+		PixelFormat::CMYK32 => None,
+	}?;
+	Some((image, width, height))
+}
+
@@ -0,0 +1,266 @@
+use std::borrow::Cow;
+
+use image::{
+	codecs::{jpeg::JpegEncoder, png::PngEncoder},
+	error::{EncodingError, ImageError},
+	ColorType, DynamicImage, GenericImageView, ImageFormat, ImageResult,
+};
+use rgb::FromSlice;
+
+use super::{optimize::optimize_png, ssim::ssim};
+use crate::{
+	config::{
+		ChromaSubsampling, Config, EncodeMode, JpegConfig, JpegEncoderKind,
+		OutputFormat,
+	},
+	pb::atwany::media::Size,
+};
+
+/// An encoded variant and the settings that produced it.
+#[derive(Debug, Clone)]
+pub struct Encoded {
+	pub buffer: Vec<u8>,
+	pub format: OutputFormat,
+	pub quality: u8,
+	/// SSIM of the encoded variant against its pixels, only measured by the
+	/// adaptive modes.
+	pub score: f64,
+	/// Size before PNG optimization, `0` when it was not optimized.
+	pub unoptimized_size: usize,
+}
+
+impl Encoded {
+	fn jpeg(buffer: Vec<u8>, quality: u8, score: f64) -> Self {
+		Self {
+			buffer,
+			format: OutputFormat::Jpeg,
+			quality,
+			score,
+			unoptimized_size: 0,
+		}
+	}
+}
+
+/// Encodes `image` for the `size` variant. JPEGs use either the configured
+/// quality or binary search the quality that meets the byte budget or the
+/// SSIM target of that variant, PNGs are lossless and get optimized instead,
+/// WebPs and AVIFs always use the configured quality.
+pub fn encode(
+	image: &DynamicImage,
+	size: Size,
+	config: &Config,
+) -> ImageResult<Encoded> {
+	let format = config.encoding.variant(size).format;
+	if let OutputFormat::Webp | OutputFormat::Avif = format {
+		let quality = config.encoding.quality;
+		let buffer = match format {
+			OutputFormat::Avif => {
+				get_avif_bytes(image, quality, config.encoding.avif_speed)?
+			},
+			_ => get_webp_bytes(image, quality),
+		};
+		return Ok(Encoded {
+			buffer,
+			format,
+			quality,
+			score: 0.0,
+			unoptimized_size: 0,
+		});
+	}
+	if format == OutputFormat::Png {
+		let optimized = optimize_png(get_png_bytes(image)?, &config.png);
+		let unoptimized_size = if optimized.buffer.len() < optimized.original_size {
+			optimized.original_size
+		} else {
+			0
+		};
+		return Ok(Encoded {
+			buffer: optimized.buffer,
+			format: OutputFormat::Png,
+			quality: 0,
+			score: 0.0,
+			unoptimized_size,
+		});
+	}
+	let jpeg = config.encoding.jpeg_for(size);
+	let config = &config.encoding;
+	let min = config.min_quality.clamp(1, 100);
+	let max = config.max_quality.max(min).min(100);
+	let encoded = match config.mode {
+		EncodeMode::Fixed => Encoded::jpeg(
+			get_image_bytes(image, config.quality, &jpeg)?,
+			config.quality,
+			0.0,
+		),
+		EncodeMode::Size => {
+			let budget = config.target_bytes_for(size);
+			let (mut lo, mut hi) = (min, max);
+			let mut best = None;
+			// output grows with quality, keep the highest one that fits
+			while lo <= hi {
+				let mid = lo + (hi - lo) / 2;
+				let buffer = get_image_bytes(image, mid, &jpeg)?;
+				if buffer.len() <= budget {
+					best = Some((mid, buffer));
+					lo = mid + 1;
+				} else if mid == min {
+					break;
+				} else {
+					hi = mid - 1;
+				}
+			}
+			let (quality, buffer) = match best {
+				Some(best) => best,
+				None => (min, get_image_bytes(image, min, &jpeg)?),
+			};
+			let score = score(image, &buffer);
+			Encoded::jpeg(buffer, quality, score)
+		},
+		EncodeMode::Quality => {
+			let (mut lo, mut hi) = (min, max);
+			let mut best = None;
+			// similarity grows with quality, keep the lowest one that passes
+			while lo <= hi {
+				let mid = lo + (hi - lo) / 2;
+				let buffer = get_image_bytes(image, mid, &jpeg)?;
+				let score = score(image, &buffer);
+				if score >= config.target_ssim {
+					best = Some(Encoded::jpeg(buffer, mid, score));
+					if mid == min {
+						break;
+					}
+					hi = mid - 1;
+				} else {
+					lo = mid + 1;
+				}
+			}
+			match best {
+				Some(best) => best,
+				None => {
+					let buffer = get_image_bytes(image, max, &jpeg)?;
+					let score = score(image, &buffer);
+					Encoded::jpeg(buffer, max, score)
+				},
+			}
+		},
+	};
+	Ok(encoded)
+}
+
+pub fn get_image_bytes(
+	image: &DynamicImage,
+	quality: u8,
+	config: &JpegConfig,
+) -> ImageResult<Vec<u8>> {
+	if let JpegEncoderKind::Mozjpeg = config.encoder {
+		// mozjpeg reports errors by unwinding, fall back to the baseline
+		// encoder rather than losing the variant
+		if let Ok(output) = get_mozjpeg_bytes(image, quality, config) {
+			return Ok(output);
+		}
+	}
+	let image = eight_bit(image);
+	let mut output = Vec::new();
+	let mut j = JpegEncoder::new_with_quality(&mut output, quality);
+	j.encode(
+		&image.to_bytes(),
+		image.width(),
+		image.height(),
+		image.color(),
+	)?;
+	Ok(output)
+}
+
+fn get_mozjpeg_bytes(
+	image: &DynamicImage,
+	quality: u8,
+	config: &JpegConfig,
+) -> Result<Vec<u8>, ()> {
+	let rgb = image.to_rgb8();
+	let (width, height) = rgb.dimensions();
+	let config = config.clone();
+	std::panic::catch_unwind(move || -> std::io::Result<Vec<u8>> {
+		let mut compress = mozjpeg::Compress::new(mozjpeg::ColorSpace::JCS_RGB);
+		compress.set_size(width as usize, height as usize);
+		compress.set_quality(f32::from(quality));
+		compress.set_optimize_coding(config.optimize_coding);
+		if config.progressive {
+			compress.set_progressive_mode();
+			compress.set_optimize_scans(true);
+		}
+		let sampling = match config.chroma_subsampling {
+			ChromaSubsampling::S420 => (2, 2),
+			ChromaSubsampling::S422 => (2, 1),
+			ChromaSubsampling::S444 => (1, 1),
+		};
+		compress.set_chroma_sampling_pixel_sizes(sampling, sampling);
+		let mut started = compress.start_compress(Vec::new())?;
+		started.write_scanlines(rgb.as_raw())?;
+		started.finish()
+	})
+	.map_err(|_| ())?
+	.map_err(|_| ())
+}
+
+pub fn get_png_bytes(image: &DynamicImage) -> ImageResult<Vec<u8>> {
+	let image = eight_bit(image);
+	let mut output = Vec::new();
+	PngEncoder::new(&mut output).encode(
+		&image.to_bytes(),
+		image.width(),
+		image.height(),
+		image.color(),
+	)?;
+	Ok(output)
+}
+
+pub fn get_webp_bytes(image: &DynamicImage, quality: u8) -> Vec<u8> {
+	let rgba = image.to_rgba8();
+	webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height())
+		.encode(f32::from(quality))
+		.to_vec()
+}
+
+/// `speed` goes from 1 (smallest) to 10 (fastest).
+pub fn get_avif_bytes(
+	image: &DynamicImage,
+	quality: u8,
+	speed: u8,
+) -> ImageResult<Vec<u8>> {
+	let rgba = image.to_rgba8();
+	let pixels = ravif::Img::new(
+		rgba.as_raw().as_rgba(),
+		rgba.width() as usize,
+		rgba.height() as usize,
+	);
+	ravif::Encoder::new()
+		.with_quality(f32::from(quality))
+		.with_alpha_quality(f32::from(quality))
+		.with_speed(speed.clamp(1, 10))
+		.with_alpha_color_mode(ravif::AlphaColorMode::UnassociatedClean)
+		.with_internal_color_model(ravif::ColorModel::YCbCr)
+		.encode_rgba(pixels)
+		.map(|encoded| encoded.avif_file)
+		.map_err(|e| {
+			ImageError::Encoding(EncodingError::new(ImageFormat::Avif.into(), e))
+		})
+}
+
+/// The encoders only take 8 bit samples, 16 bit uploads are narrowed first.
+fn eight_bit(image: &DynamicImage) -> Cow<'_, DynamicImage> {
+	match image.color() {
+		ColorType::L8 | ColorType::Rgb8 | ColorType::Rgba8 => {
+			Cow::Borrowed(image)
+		},
+		ColorType::La8 | ColorType::La16 | ColorType::Rgba16 | ColorType::Bgra8 => {
+			Cow::Owned(DynamicImage::ImageRgba8(image.to_rgba8()))
+		},
+		_ => Cow::Owned(DynamicImage::ImageRgb8(image.to_rgb8())),
+	}
+}
+
+fn score(image: &DynamicImage, buffer: &[u8]) -> f64 {
+	image::load_from_memory_with_format(buffer, ImageFormat::Jpeg)
+		.map(|encoded| ssim(image, &encoded))
+		.unwrap_or(0.0)
+}
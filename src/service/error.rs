@@ -0,0 +1,214 @@
+use std::{collections::HashMap, io, path::PathBuf, time::Duration};
+
+use bytes::Bytes;
+use log::error;
+use prost::Message;
+use thiserror::Error;
+use tonic::{Code, Status};
+
+use crate::pb::atwany::media::{ErrorDetail, Size};
+
+/// Reported as `ErrorDetail.domain`.
+const DOMAIN: &str = "atwany";
+/// `ENOSPC`, the volume is full.
+const NO_SPACE: i32 = 28;
+/// Seconds a throttled client should wait, like the HTTP header.
+const RETRY_AFTER: &str = "retry-after";
+
+/// Everything that can go wrong while handling a media request. Each variant
+/// maps to the gRPC code a client can act on, and carries an [`ErrorDetail`]
+/// in the status details.
+#[derive(Debug, Error)]
+pub enum MediaError {
+	#[error("{0}")]
+	InvalidArgument(String),
+	#[error("{0}")]
+	PermissionDenied(String),
+	#[error("{0}")]
+	Unauthenticated(String),
+	#[error("{0} not found")]
+	NotFound(String),
+	#[error("failed to decode image: {0}")]
+	Decode(#[source] image::ImageError),
+	#[error("failed to encode the {} variant: {source}", .size.to_string())]
+	Encode {
+		size: Size,
+		#[source]
+		source: image::ImageError,
+	},
+	#[error("failed to render image: {0}")]
+	Render(#[source] image::ImageError),
+	#[error("image processing queue is full, retry later")]
+	Overloaded,
+	#[error("image processing job panicked")]
+	WorkerFailed,
+	#[error("failed to write {}: {source}", .path.display())]
+	Storage {
+		path: PathBuf,
+		#[source]
+		source: io::Error,
+	},
+	#[error("failed to render placeholder")]
+	Placeholder,
+	/// A bug, not something the client can fix.
+	#[error("internal error: {0}")]
+	Internal(String),
+	#[error("private media needs a signing key, see http.signing")]
+	SigningDisabled,
+	#[error("{resource} quota of {limit} exceeded, {used} used")]
+	QuotaExceeded {
+		resource: &'static str,
+		used: u64,
+		limit: u64,
+	},
+	#[error("{scope} rate limit exceeded, retry later")]
+	RateLimited {
+		/// `principal` or `tenant`.
+		scope: &'static str,
+		retry_after: Duration,
+	},
+	#[error("server overloaded, retry later")]
+	Shed { retry_after: Duration },
+}
+
+impl MediaError {
+	pub fn storage(path: impl Into<PathBuf>, source: io::Error) -> Self {
+		MediaError::Storage {
+			path: path.into(),
+			source,
+		}
+	}
+
+	pub fn code(&self) -> Code {
+		match self {
+			MediaError::InvalidArgument(_) | MediaError::Decode(_) => {
+				Code::InvalidArgument
+			},
+			MediaError::NotFound(_) => Code::NotFound,
+			MediaError::PermissionDenied(_) => Code::PermissionDenied,
+			MediaError::Unauthenticated(_) => Code::Unauthenticated,
+			MediaError::Overloaded
+			| MediaError::QuotaExceeded { .. }
+			| MediaError::RateLimited { .. }
+			| MediaError::Shed { .. } => Code::ResourceExhausted,
+			MediaError::SigningDisabled => Code::FailedPrecondition,
+			MediaError::Storage { source, .. }
+				if source.raw_os_error() == Some(NO_SPACE) =>
+			{
+				Code::ResourceExhausted
+			},
+			MediaError::Storage { .. } => Code::Unavailable,
+			MediaError::Encode { .. }
+			| MediaError::Render(_)
+			| MediaError::WorkerFailed
+			| MediaError::Placeholder
+			| MediaError::Internal(_) => Code::Internal,
+		}
+	}
+
+	/// Machine readable reason, stable across releases.
+	pub const fn reason(&self) -> &'static str {
+		match self {
+			MediaError::InvalidArgument(_) => "INVALID_ARGUMENT",
+			MediaError::NotFound(_) => "NOT_FOUND",
+			MediaError::PermissionDenied(_) => "PERMISSION_DENIED",
+			MediaError::Unauthenticated(_) => "UNAUTHENTICATED",
+			MediaError::Decode(_) => "UNSUPPORTED_IMAGE",
+			MediaError::Encode { .. } => "ENCODE_FAILED",
+			MediaError::Render(_) => "RENDER_FAILED",
+			MediaError::Overloaded => "QUEUE_FULL",
+			MediaError::WorkerFailed => "WORKER_FAILED",
+			MediaError::Storage { .. } => "STORAGE_FAILED",
+			MediaError::Placeholder => "PLACEHOLDER_FAILED",
+			MediaError::Internal(_) => "INTERNAL",
+			MediaError::SigningDisabled => "SIGNING_DISABLED",
+			MediaError::QuotaExceeded { .. } => "QUOTA_EXCEEDED",
+			MediaError::RateLimited { .. } => "RATE_LIMITED",
+			MediaError::Shed { .. } => "LOAD_SHED",
+		}
+	}
+
+	fn metadata(&self) -> HashMap<String, String> {
+		let mut metadata = HashMap::new();
+		match self {
+			MediaError::Encode { size, .. } => {
+				metadata.insert("size".to_string(), size.to_string());
+			},
+			MediaError::QuotaExceeded {
+				resource,
+				used,
+				limit,
+			} => {
+				metadata.insert("resource".to_string(), resource.to_string());
+				metadata.insert("used".to_string(), used.to_string());
+				metadata.insert("limit".to_string(), limit.to_string());
+			},
+			MediaError::RateLimited { scope, .. } => {
+				metadata.insert("scope".to_string(), scope.to_string());
+			},
+			_ => {},
+		}
+		if let Some(retry_after) = self.retry_after() {
+			let ms = retry_after.as_millis().to_string();
+			metadata.insert("retry_after_ms".to_string(), ms);
+		}
+		metadata
+	}
+
+	/// What the client is told. Storage errors name server paths and OS
+	/// errors, those are only logged.
+	fn message(&self) -> String {
+		match self {
+			MediaError::Storage { .. } => {
+				error!("{}", self);
+				match self.code() {
+					Code::ResourceExhausted => "storage is full".to_string(),
+					_ => "storage is unavailable".to_string(),
+				}
+			},
+			_ => self.to_string(),
+		}
+	}
+
+	/// How long a throttled client should back off.
+	pub const fn retry_after(&self) -> Option<Duration> {
+		match self {
+			MediaError::RateLimited { retry_after, .. }
+			| MediaError::Shed { retry_after } => Some(*retry_after),
+			_ => None,
+		}
+	}
+
+	/// [`retry_after`](Self::retry_after) in whole seconds, rounded up so a
+	/// client retrying right when told isn't throttled again.
+	pub fn retry_after_secs(&self) -> Option<u64> {
+		self.retry_after().map(|retry_after| {
+			let subsec = retry_after.subsec_nanos() > 0;
+			retry_after.as_secs() + u64::from(subsec)
+		})
+	}
+}
+
+impl From<MediaError> for Status {
+	fn from(error: MediaError) -> Self {
+		let detail = ErrorDetail {
+			reason: error.reason().to_string(),
+			domain: DOMAIN.to_string(),
+			metadata: error.metadata(),
+		};
+		let mut details = Vec::with_capacity(detail.encoded_len());
+		// encoding into a Vec can't run out of space
+		let _ = detail.encode(&mut details);
+		let mut status = Status::with_details(
+			error.code(),
+			error.message(),
+			Bytes::from(details),
+		);
+		if let Some(secs) = error.retry_after_secs() {
+			if let Ok(value) = secs.to_string().parse() {
+				status.metadata_mut().insert(RETRY_AFTER, value);
+			}
+		}
+		status
+	}
+}
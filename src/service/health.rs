@@ -0,0 +1,166 @@
+use std::{fs, path::Path, sync::Arc, time::Duration};
+
+use futures::{channel::mpsc, SinkExt};
+use log::{debug, warn};
+use tokio::sync::watch;
+use tonic::{Request, Response, Status};
+
+use super::{error::MediaError, pool::WorkerPool};
+use crate::{
+	config::Config,
+	pb::health::{
+		health_check_response::ServingStatus, health_server::Health,
+		HealthCheckRequest, HealthCheckResponse,
+	},
+};
+pub use crate::pb::health::health_server::HealthServer;
+
+/// Services whose health is reported, the empty name stands for the server.
+const SERVICES: [&str; 2] = ["", "atwany.Media"];
+/// Written and removed again to tell whether a directory is writable.
+const PROBE: &str = ".health-probe";
+
+/// `grpc.health.v1.Health`, answering from the status [`Checker`] last
+/// found.
+#[derive(Debug)]
+pub struct HealthService {
+	status: watch::Receiver<ServingStatus>,
+}
+
+/// Finds out whether the server can take uploads: its storage is writable
+/// and its worker pool isn't turning jobs away.
+#[derive(Debug)]
+pub struct Checker {
+	config: Arc<Config>,
+	pool: Arc<WorkerPool>,
+	status: watch::Sender<ServingStatus>,
+}
+
+impl HealthService {
+	/// The service, and the checker that keeps its status current.
+	pub fn new(
+		config: Arc<Config>,
+		pool: Arc<WorkerPool>,
+	) -> (Self, Checker) {
+		let (tx, rx) = watch::channel(ServingStatus::Unknown);
+		let checker = Checker {
+			config,
+			pool,
+			status: tx,
+		};
+		(Self { status: rx }, checker)
+	}
+
+	fn current(&self) -> ServingStatus { *self.status.borrow() }
+}
+
+impl Checker {
+	/// Checks every `interval` until the service is dropped.
+	pub async fn run(self, interval: Duration) {
+		let mut ticker = tokio::time::interval(interval);
+		let mut last = ServingStatus::Unknown;
+		loop {
+			ticker.tick().await;
+			let status = self.check().await;
+			if status != last {
+				debug!("health: {:?}", status);
+				last = status;
+			}
+			if self.status.broadcast(status).is_err() {
+				return;
+			}
+		}
+	}
+
+	async fn check(&self) -> ServingStatus {
+		let storage = self.config.storage.clone();
+		let writable = tokio::task::spawn_blocking(move || {
+			[&storage.images_dir, &storage.files_dir]
+				.iter()
+				.all(|dir| match probe(dir) {
+					Ok(()) => true,
+					Err(e) => {
+						warn!("Health check failed: {}", e);
+						false
+					},
+				})
+		})
+		.await
+		.unwrap_or(false);
+		// a full queue turns new jobs away with `ResourceExhausted`
+		let stats = self.pool.stats();
+		let saturated =
+			stats.running >= stats.workers && stats.queued >= stats.max_queue;
+		if writable && !saturated {
+			ServingStatus::Serving
+		} else {
+			ServingStatus::NotServing
+		}
+	}
+}
+
+/// Writes a file in `dir` and removes it again.
+fn probe(dir: &Path) -> Result<(), MediaError> {
+	let path = dir.join(PROBE);
+	fs::create_dir_all(dir)
+		.and_then(|()| fs::write(&path, b"ok"))
+		.and_then(|()| fs::remove_file(&path))
+		.map_err(|e| MediaError::storage(&path, e))
+}
+
+fn response(status: ServingStatus) -> HealthCheckResponse {
+	HealthCheckResponse {
+		status: status.into(),
+	}
+}
+
+#[tonic::async_trait]
+impl Health for HealthService {
+	async fn check(
+		&self,
+		request: Request<HealthCheckRequest>,
+	) -> Result<Response<HealthCheckResponse>, Status> {
+		let service = request.into_inner().service;
+		if !SERVICES.contains(&service.as_str()) {
+			return Err(MediaError::NotFound(service).into());
+		}
+		Ok(Response::new(response(self.current())))
+	}
+
+	type WatchStream = mpsc::Receiver<Result<HealthCheckResponse, Status>>;
+
+	async fn watch(
+		&self,
+		request: Request<HealthCheckRequest>,
+	) -> Result<Response<Self::WatchStream>, Status> {
+		let service = request.into_inner().service;
+		// the stream stays open for unknown services, as the protocol asks
+		let unknown = !SERVICES.contains(&service.as_str());
+		let (mut tx, rx) = mpsc::channel(1);
+		let mut status = self.status.clone();
+		tokio::spawn(async move {
+			let mut sent = None;
+			// yields the current status first, then one per check
+			while let Some(current) = status.recv().await {
+				let current = if unknown {
+					ServingStatus::ServiceUnknown
+				} else {
+					current
+				};
+				if tx.is_closed() {
+					debug!("health watch closed by the client");
+					return;
+				}
+				if sent == Some(current) {
+					continue;
+				}
+				sent = Some(current);
+				if tx.send(Ok(response(current))).await.is_err() {
+					debug!("health watch closed by the client");
+					return;
+				}
+			}
+		});
+		Ok(Response::new(rx))
+	}
+}
@@ -0,0 +1,186 @@
+use std::{
+	fs, io,
+	path::{Path, PathBuf},
+};
+
+use super::{error::MediaError, meta::sha256_hex};
+use crate::{
+	config::{size_key, StorageConfig, StorageLayout},
+	pb::atwany::media::Size,
+};
+
+const MANIFEST: &str = "manifest.toml";
+
+/// Every variant a media set can hold.
+pub const SIZES: [Size; 5] = [
+	Size::Original,
+	Size::Medium,
+	Size::Small,
+	Size::Thumbnail,
+	Size::Placeholder,
+];
+
+/// A variant from either its URL suffix (`md`, `sm-400`, ...) or its config
+/// name (`medium`, `small`, ...).
+pub fn parse_size(name: &str) -> Option<Size> {
+	SIZES
+		.iter()
+		.copied()
+		.find(|size| size.to_string() == name || size_key(*size) == name)
+}
+
+/// Ids end up in paths, they can't climb out of the images directory.
+pub fn is_valid_id(id: &str) -> bool {
+	!id.is_empty()
+		&& !id.starts_with('.')
+		&& !id.contains(|c| matches!(c, '/' | '\\' | '\0'))
+}
+
+/// [`is_valid_id`] for the handlers, the paths below take `id` as is.
+pub fn check_id(id: &str) -> Result<(), MediaError> {
+	if is_valid_id(id) {
+		Ok(())
+	} else {
+		let msg = format!("invalid media id {:?}", id);
+		Err(MediaError::InvalidArgument(msg))
+	}
+}
+
+/// Where the `size` variant of media `id` is stored.
+pub fn image_path(
+	config: &StorageConfig,
+	id: &str,
+	size: Size,
+	ext: &str,
+) -> PathBuf {
+	match config.layout {
+		StorageLayout::Flat => config
+			.images_dir
+			.join(format!("{}_{}.{}", id, size, ext)),
+		StorageLayout::Sharded => sharded_path(config, id, size, ext),
+	}
+}
+
+pub fn sharded_path(
+	config: &StorageConfig,
+	id: &str,
+	size: Size,
+	ext: &str,
+) -> PathBuf {
+	media_dir(config, id).join(format!("{}.{}", size, ext))
+}
+
+/// Directory holding every variant of media `id` in the sharded layout.
+pub fn media_dir(config: &StorageConfig, id: &str) -> PathBuf {
+	let hash = sha256_hex(id.as_bytes());
+	config
+		.images_dir
+		.join(&hash[0..2])
+		.join(&hash[2..4])
+		.join(id)
+}
+
+/// Where the [`Manifest`](super::manifest::Manifest) of media `id` is
+/// stored.
+pub fn manifest_path(config: &StorageConfig, id: &str) -> PathBuf {
+	match config.layout {
+		StorageLayout::Flat => flat_manifest_path(config, id),
+		StorageLayout::Sharded => sharded_manifest_path(config, id),
+	}
+}
+
+pub fn sharded_manifest_path(config: &StorageConfig, id: &str) -> PathBuf {
+	media_dir(config, id).join(MANIFEST)
+}
+
+pub fn flat_manifest_path(config: &StorageConfig, id: &str) -> PathBuf {
+	config.images_dir.join(format!("{}.{}", id, MANIFEST))
+}
+
+/// Id of a manifest stored in the flat layout.
+pub fn parse_flat_manifest(path: &Path) -> Option<String> {
+	let name = path.file_name()?.to_str()?;
+	let id = name.strip_suffix(&format!(".{}", MANIFEST))?;
+	if id.is_empty() || id.starts_with('.') {
+		return None;
+	}
+	Some(id.to_string())
+}
+
+/// Id and path of every stored original, in a stable order.
+pub fn originals(config: &StorageConfig) -> io::Result<Vec<(String, PathBuf)>> {
+	let mut originals = Vec::new();
+	match config.layout {
+		StorageLayout::Flat => {
+			for entry in fs::read_dir(&config.images_dir)? {
+				let path = entry?.path();
+				if let Some((id, Size::Original, _)) = parse_flat(&path) {
+					originals.push((id, path));
+				}
+			}
+		},
+		StorageLayout::Sharded => {
+			let prefix = format!("{}.", Size::Original);
+			// skips the tenants' directory, among others
+			let shards = sub_dirs(&config.images_dir)?;
+			for a in shards.into_iter().filter(|a| is_shard(a)) {
+				for b in sub_dirs(&a)? {
+					for dir in sub_dirs(&b)? {
+						let id = match dir.file_name().and_then(|id| id.to_str()) {
+							Some(id) => id.to_string(),
+							None => continue,
+						};
+						for entry in fs::read_dir(&dir)? {
+							let path = entry?.path();
+							let is_original = path
+								.file_name()
+								.and_then(|name| name.to_str())
+								.map_or(false, |name| name.starts_with(&prefix));
+							if is_original {
+								originals.push((id.clone(), path));
+							}
+						}
+					}
+				}
+			}
+		},
+	}
+	originals.sort();
+	Ok(originals)
+}
+
+/// The first level of the sharded layout, two hex digits.
+fn is_shard(dir: &Path) -> bool {
+	dir.file_name()
+		.and_then(|name| name.to_str())
+		.map_or(false, |name| {
+			name.len() == 2 && name.bytes().all(|b| b.is_ascii_hexdigit())
+		})
+}
+
+fn sub_dirs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+	let mut dirs = Vec::new();
+	for entry in fs::read_dir(dir)? {
+		let entry = entry?;
+		if entry.file_type()?.is_dir() {
+			dirs.push(entry.path());
+		}
+	}
+	Ok(dirs)
+}
+
+/// Splits a flat layout file name back into its id, variant and extension.
+pub fn parse_flat(path: &Path) -> Option<(String, Size, String)> {
+	let name = path.file_name()?.to_str()?;
+	if name.starts_with('.') {
+		return None;
+	}
+	let (stem, ext) = name.rsplit_once('.')?;
+	SIZES.iter().find_map(|size| {
+		let id = stem.strip_suffix(&format!("_{}", size))?;
+		if id.is_empty() {
+			return None;
+		}
+		Some((id.to_string(), *size, ext.to_string()))
+	})
+}
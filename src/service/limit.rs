@@ -0,0 +1,228 @@
+use std::{
+	collections::HashMap,
+	fs,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc, Mutex,
+	},
+	time::{Duration, Instant},
+};
+
+use log::warn;
+use tonic::{Request, Status};
+
+use super::{
+	auth::Principal, error::MediaError, pool::WorkerPool, tenant::Tenants,
+};
+use crate::config::{LimitsConfig, RateLimit, SheddingConfig};
+
+/// Buckets kept before the idle ones are forgotten.
+const MAX_BUCKETS: usize = 10_000;
+/// How often [`LoadShedder::monitor`] samples the memory use.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+	tokens: f64,
+	updated: Instant,
+}
+
+impl Bucket {
+	fn full(limit: &RateLimit, now: Instant) -> Self {
+		Self {
+			tokens: capacity(limit),
+			updated: now,
+		}
+	}
+
+	fn refill(&mut self, limit: &RateLimit, now: Instant) {
+		let elapsed = now.saturating_duration_since(self.updated);
+		self.tokens = (self.tokens + elapsed.as_secs_f64() * limit.rate)
+			.min(capacity(limit));
+		self.updated = now;
+	}
+
+	/// Takes a token, or tells how long until there is one.
+	fn take(
+		&mut self,
+		limit: &RateLimit,
+		now: Instant,
+	) -> Result<(), Duration> {
+		self.refill(limit, now);
+		if self.tokens >= 1.0 {
+			self.tokens -= 1.0;
+			Ok(())
+		} else {
+			Err(Duration::from_secs_f64((1.0 - self.tokens) / limit.rate))
+		}
+	}
+}
+
+fn capacity(limit: &RateLimit) -> f64 {
+	if limit.burst == 0 {
+		limit.rate.max(1.0)
+	} else {
+		f64::from(limit.burst)
+	}
+}
+
+type Buckets = Mutex<HashMap<String, Bucket>>;
+
+/// Token buckets for every principal and tenant, see
+/// [`LimitsConfig`](crate::config::LimitsConfig).
+#[derive(Debug)]
+pub struct RateLimiter {
+	config: LimitsConfig,
+	tenants: Arc<Tenants>,
+	principals: Buckets,
+	by_tenant: Buckets,
+}
+
+impl RateLimiter {
+	pub fn new(config: LimitsConfig, tenants: Arc<Tenants>) -> Self {
+		Self {
+			config,
+			tenants,
+			principals: Mutex::new(HashMap::new()),
+			by_tenant: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Rate limits a call for the generated server's `with_interceptor`,
+	/// once [`Authenticator::interceptor`](super::auth::Authenticator)
+	/// identified its principal.
+	pub fn interceptor(
+		self: Arc<Self>,
+	) -> impl Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync + 'static {
+		move |request| {
+			self.check(&request)?;
+			Ok(request)
+		}
+	}
+
+	fn check(&self, request: &Request<()>) -> Result<(), MediaError> {
+		let now = Instant::now();
+		let principal = Principal::of(request).to_string();
+		let limit = &self.config.per_principal;
+		take(&self.principals, limit, principal, now)
+			.map_err(|retry_after| MediaError::RateLimited {
+				scope: "principal",
+				retry_after,
+			})?;
+		// calls for unknown tenants are turned away by the handlers
+		if let Ok(tenant) = self.tenants.of(request) {
+			let limit = &self.config.per_tenant;
+			take(&self.by_tenant, limit, tenant.name.clone(), now).map_err(
+				|retry_after| MediaError::RateLimited {
+					scope: "tenant",
+					retry_after,
+				},
+			)?;
+		}
+		Ok(())
+	}
+}
+
+fn take(
+	buckets: &Buckets,
+	limit: &RateLimit,
+	key: String,
+	now: Instant,
+) -> Result<(), Duration> {
+	if limit.rate <= 0.0 {
+		return Ok(());
+	}
+	let mut buckets = buckets
+		.lock()
+		.unwrap_or_else(|poisoned| poisoned.into_inner());
+	if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&key) {
+		// a refilled bucket is the same as a new one
+		let capacity = capacity(limit);
+		buckets.retain(|_, bucket| {
+			bucket.refill(limit, now);
+			bucket.tokens < capacity
+		});
+	}
+	buckets
+		.entry(key)
+		.or_insert_with(|| Bucket::full(limit, now))
+		.take(limit, now)
+}
+
+/// Turns calls that need the worker pool away as it, or the memory of the
+/// process, fills up, see [`SheddingConfig`](crate::config::SheddingConfig).
+#[derive(Debug)]
+pub struct LoadShedder {
+	config: SheddingConfig,
+	pool: Arc<WorkerPool>,
+	/// Resident memory, in bytes, at the last sample.
+	memory: AtomicU64,
+}
+
+impl LoadShedder {
+	pub fn new(config: SheddingConfig, pool: Arc<WorkerPool>) -> Self {
+		Self {
+			config,
+			pool,
+			memory: AtomicU64::new(0),
+		}
+	}
+
+	/// Sheds a share of the calls that grows with the load past the
+	/// threshold.
+	pub fn admit(&self) -> Result<(), MediaError> {
+		if !self.config.enabled {
+			return Ok(());
+		}
+		let (load, threshold) = (self.load(), self.config.threshold);
+		if load <= threshold {
+			return Ok(());
+		}
+		let share = if threshold < 1.0 {
+			(load - threshold) / (1.0 - threshold)
+		} else {
+			1.0
+		};
+		if rand::random::<f64>() >= share {
+			return Ok(());
+		}
+		Err(MediaError::Shed {
+			retry_after: Duration::from_millis(self.config.retry_after_ms),
+		})
+	}
+
+	/// The fuller of the worker pool and the memory budget, 1 is full.
+	fn load(&self) -> f64 {
+		let stats = self.pool.stats();
+		let slots = (stats.workers + stats.max_queue).max(1);
+		let pool = (stats.running + stats.queued) as f64 / slots as f64;
+		let memory = match self.config.max_memory_bytes {
+			0 => 0.0,
+			max => self.memory.load(Ordering::SeqCst) as f64 / max as f64,
+		};
+		pool.max(memory)
+	}
+
+	/// Samples the memory use of the process until it exits.
+	pub async fn monitor(self: Arc<Self>) {
+		let mut ticker = tokio::time::interval(SAMPLE_INTERVAL);
+		loop {
+			ticker.tick().await;
+			match resident_memory() {
+				Some(bytes) => self.memory.store(bytes, Ordering::SeqCst),
+				None => {
+					warn!("Memory use is unknown, shedding on the pool only");
+					return;
+				},
+			}
+		}
+	}
+}
+
+/// `VmRSS` of the process, only known on Linux.
+fn resident_memory() -> Option<u64> {
+	let status = fs::read_to_string("/proc/self/status").ok()?;
+	let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+	let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+	Some(kb * 1024)
+}
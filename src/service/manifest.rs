@@ -0,0 +1,145 @@
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+	error::MediaError, media::size_dimension, meta::sha256_hex,
+	storage::Pending,
+};
+use crate::{
+	config::{size_key, Config, OutputFormat},
+	pb::atwany::media::{Size, UploadResponse},
+};
+
+/// Stored next to the variants of a media, records which preset produced
+/// each of them so reprocessing only rewrites what changed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Manifest {
+	/// Only served through signed URLs. Comes first, TOML wants plain values
+	/// before tables.
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub private: bool,
+	/// Keyed by the config name of the variant, see [`size_key`].
+	pub variants: BTreeMap<String, Entry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+	pub fingerprint: String,
+	pub extension: String,
+	pub sha256: String,
+}
+
+impl Manifest {
+	/// A missing or unreadable manifest reads as empty, every variant is then
+	/// considered outdated.
+	pub fn read(path: &Path) -> Self {
+		fs::read_to_string(path)
+			.ok()
+			.and_then(|raw| toml::from_str(&raw).ok())
+			.unwrap_or_default()
+	}
+
+	/// Like [`read`](Self::read), but only a missing manifest reads as empty.
+	/// Privacy checks and rewrites use it, an unreadable manifest must not
+	/// turn private media public.
+	pub fn load(path: &Path) -> Result<Self, MediaError> {
+		let raw = match fs::read_to_string(path) {
+			Ok(raw) => raw,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {
+				return Ok(Self::default())
+			},
+			Err(e) => return Err(MediaError::storage(path, e)),
+		};
+		toml::from_str(&raw).map_err(|e| {
+			let e = io::Error::new(io::ErrorKind::InvalidData, e);
+			MediaError::storage(path, e)
+		})
+	}
+
+	pub fn get(&self, size: Size) -> Option<&Entry> {
+		self.variants.get(size_key(size))
+	}
+
+	/// A variant rendered in another format than its own, for content
+	/// negotiation.
+	pub fn get_alternate(&self, size: Size, ext: &str) -> Option<&Entry> {
+		self.variants.get(&alternate_key(size, ext))
+	}
+
+	pub fn record_alternate(&mut self, res: &UploadResponse, config: &Config) {
+		let size = Size::from_i32(res.size).unwrap_or(Size::Original);
+		self.variants.insert(alternate_key(size, &res.file_extension), Entry {
+			fingerprint: fingerprint(config, size),
+			extension: res.file_extension.clone(),
+			sha256: res.sha256.clone(),
+		});
+	}
+
+	/// Forgets the alternates of `size`, returning their extensions.
+	pub fn remove_alternates(&mut self, size: Size) -> Vec<String> {
+		let prefix = alternate_key(size, "");
+		let keys: Vec<String> = self
+			.variants
+			.keys()
+			.filter(|key| key.starts_with(&prefix))
+			.cloned()
+			.collect();
+		keys.into_iter()
+			.filter_map(|key| self.variants.remove(&key))
+			.map(|entry| entry.extension)
+			.collect()
+	}
+
+	/// Records the variants in `responses` as produced by `config`.
+	pub fn record(&mut self, responses: &[UploadResponse], config: &Config) {
+		for res in responses {
+			let size = Size::from_i32(res.size).unwrap_or(Size::Original);
+			self.variants.insert(size_key(size).to_string(), Entry {
+				fingerprint: fingerprint(config, size),
+				extension: res.file_extension.clone(),
+				sha256: res.sha256.clone(),
+			});
+		}
+	}
+
+	/// The manifest as a file for [`commit`](super::storage::commit), so it
+	/// lands together with the variants it describes.
+	pub fn to_pending(&self, path: &Path) -> Pending {
+		Pending {
+			path: path.to_path_buf(),
+			// plain maps of strings always serialize
+			buffer: toml::to_string(self).unwrap_or_default().into_bytes(),
+		}
+	}
+}
+
+fn alternate_key(size: Size, ext: &str) -> String {
+	format!("{}.{}", size_key(size), ext)
+}
+
+/// Identifies the settings that shape the `size` variant. Any config change
+/// that would produce a different file changes it.
+pub fn fingerprint(config: &Config, size: Size) -> String {
+	let encoding = &config.encoding;
+	let variant = encoding.variant(size);
+	let mut preset = format!(
+		"{}|{:?}|{:?}|{:?}|{}|{}|{}|{}|{}",
+		size_dimension(size),
+		variant.format,
+		encoding.jpeg_for(size),
+		encoding.mode,
+		encoding.quality,
+		encoding.min_quality,
+		encoding.max_quality,
+		encoding.target_ssim,
+		encoding.target_bytes_for(size),
+	);
+	if variant.format == OutputFormat::Png {
+		preset.push_str(&format!("|{:?}", config.png));
+	}
+	if size == Size::Original {
+		preset.push_str(&format!("|{:?}", config.original.mode));
+	}
+	sha256_hex(preset.as_bytes())[..16].to_string()
+}
@@ -1,14 +1,9 @@
-use std::{
-	env, fs,
-	io::{ Write},
-	path,
-};
+use std::{fmt, path};
 
-use futures::{channel::mpsc, SinkExt, TryFutureExt};
+use futures::{channel::mpsc, SinkExt};
 
-use image::{DynamicImage, GenericImageView};
-use tokio::io::AsyncWriteExt;
-use tokio::task::JoinHandle;
+use image::GenericImageView;
+use log::{debug, info};
 use tonic::{Request, Response, Status};
 
 use crate::pb::atwany::{
@@ -16,9 +11,58 @@ use crate::pb::atwany::{
 	media_server::Media,
 };
 pub use crate::pb::atwany::media_server::MediaServer;
-use image::codecs::jpeg::JpegEncoder;
+use crate::config::Config;
+use std::sync::Arc;
+
+use super::placeholder::{
+	decode_placeholder, encode_placeholder, gen_placeholders,
+	inline_placeholder, to_data_uri,
+};
+use super::{
+	auth::Principal,
+	decode::{decode, Source},
+	layout::{check_id, image_path, is_valid_id, manifest_path},
+	manifest::Manifest,
+	encode::{encode, Encoded},
+	error::MediaError,
+	limit::LoadShedder,
+	meta::{mime_type, sha256_hex},
+	optimize::{is_png, optimize_png},
+	pool::WorkerPool,
+	reprocess,
+	resize::cascade,
+	signing::{sign_transform_url, sign_url},
+	sqip::gen_svg_placeholder,
+	storage::Pending,
+	tenant::Tenants,
+	usage::Meter,
+};
+use crate::config::{Generation, OriginalMode, OutputFormat, StorageConfig};
+use std::time::Duration;
+
+/// Resources the tenant uses beyond their soft quota, comma separated.
+const QUOTA_WARNING: &str = "x-atwany-quota-warning";
 
-pub struct MediaService;
+#[derive(Debug)]
+pub struct MediaService {
+	tenants: Arc<Tenants>,
+	pool: Arc<WorkerPool>,
+	shedder: Arc<LoadShedder>,
+}
+
+impl MediaService {
+	pub fn new(
+		tenants: Arc<Tenants>,
+		pool: Arc<WorkerPool>,
+		shedder: Arc<LoadShedder>,
+	) -> Self {
+		Self {
+			tenants,
+			pool,
+			shedder,
+		}
+	}
+}
 
 #[tonic::async_trait]
 impl Media for MediaService {
@@ -28,17 +72,31 @@ impl Media for MediaService {
 		&self,
 		request: Request<UploadRequest>,
 	) -> Result<Response<Self::UploadStream>, Status> {
+		self.shedder.admit()?;
+		let tenant = self.tenants.of(&request)?;
 		let req = request.into_inner();
+		tenant.check_size(req.image.len())?;
+		tenant.usage.admit()?;
 		let (mut tx, rx) = mpsc::channel(4);
-		let img = image::load_from_memory(&req.image)
-			.map_err(|_| {
-				Status::internal("Failed to obtain image for blur hashing")
-			})?;
+		let config = tenant.config.clone();
+		let usage = tenant.usage.clone();
+		let img =
+			decode_image(&self.pool, &usage, req.image, config.original.mode)
+				.await?;
+		tenant.check_format(img.extension())?;
+		let pool = self.pool.clone();
 
 		tokio::spawn(async move {
-			let res = process(img).await.unwrap();
+			let job = move || process(&img, &config, &SIZE);
+			let res = match usage.run(&pool, job).await {
+				Ok(Ok(res)) => res.into_iter().map(Ok).collect(),
+				Ok(Err(e)) | Err(e) => vec![Err(e.into())],
+			};
 			for res_slice in res {
-				tx.send(Ok(res_slice)).await.unwrap();
+				if tx.send(res_slice).await.is_err() {
+					debug!("upload stream closed by the client");
+					break;
+				}
 			}
 		});
 		Ok(Response::new(rx))
@@ -48,166 +106,525 @@ impl Media for MediaService {
 		&self,
 		request: Request<FileUpload>,
 	) -> Result<Response<FileUploadResponse>, Status> {
+		self.shedder.admit()?;
+		let principal = Principal::of(&request);
+		let tenant = self.tenants.of(&request)?;
 		let req = request.into_inner();
 		let file_name = req.file_name.clone();
 		let ext = req.file_extension.clone();
-		let path = create_file_path(&file_name, &ext);
-		let mut file = fs::File::create(path)
-			.map_err(|e| Status::internal(e.to_string()))?;
-		file.write_all(&req.file)
-			.map_err(|e| Status::internal(e.to_string()))?;
-		file.flush().map_err(|e| Status::internal(e.to_string()))?;
-		Ok(Response::new(FileUploadResponse {
+		let path = create_file_path(&tenant.config.storage, &file_name, &ext)?;
+		tenant.check_size(req.file.len())?;
+		tenant.check_file_extension(&ext)?;
+		tenant.usage.admit()?;
+		debug!("{} uploads file {}.{}", principal, file_name, ext);
+		let original_size = req.file.len() as u64;
+		let png = &tenant.config.png;
+		let contents = if png.files
+			&& (png.optimize || png.quantize)
+			&& is_png(&ext, &req.file)
+		{
+			let png = png.clone();
+			let buffer = req.file;
+			tenant
+				.usage
+				.run(&self.pool, move || optimize_png(buffer, &png).buffer)
+				.await?
+		} else {
+			req.file
+		};
+		let stored_size = contents.len() as u64;
+		let sha256 = sha256_hex(&contents);
+		let file = Pending {
+			path,
+			buffer: contents,
+		};
+		let usage = tenant.usage.clone();
+		tokio::task::spawn_blocking(move || usage.commit(vec![file]))
+			.await
+			.map_err(|_| MediaError::WorkerFailed)??;
+		let mut response = Response::new(FileUploadResponse {
+			mime_type: mime_type(&ext).to_string(),
 			file_extension: ext,
-		}))
+			original_size,
+			stored_size,
+			sha256,
+		});
+		warn_quota(&mut response, &tenant.usage);
+		Ok(response)
 	}
 
 	async fn upload_and_write(
 		&self,
 		request: Request<UploadRequest>,
 	) -> Result<Response<UploadAndWriteResponse>, Status> {
+		self.shedder.admit()?;
+		let principal = Principal::of(&request);
+		let tenant = self.tenants.of(&request)?;
+		let config = &tenant.config;
 		let req = request.into_inner();
 		let file_name = req.file_name.clone();
-		let img = image::load_from_memory(&req.image)
-			.map_err(|_| {
-				Status::internal("Failed to obtain image for blur hashing")
-			})?;
-
+		check_id(&file_name)?;
+		tenant.check_size(req.image.len())?;
+		tenant.usage.admit()?;
+		debug!("{} uploads {}", principal, file_name);
+		let inline = req.inline_placeholder;
+		let private = req.private;
+		if private && config.http.signing.keys.is_empty() {
+			return Err(MediaError::SigningDisabled.into());
+		}
+		let placeholder_config = config.placeholder.clone();
+		let svg_config = placeholder_config.svg.clone();
+		let with_svg = svg_config.enabled || req.svg_placeholder;
+		let usage = &tenant.usage;
+		let img =
+			decode_image(&self.pool, usage, req.image, config.original.mode)
+				.await?;
+		tenant.check_format(img.extension())?;
 
-		let (response_buffers, blur_hash) = tokio::join!(
-					process(img.clone()),
-					gen_blur_hash(img.clone())
+		let (response_buffers, placeholders, svg_placeholder) = tokio::join!(
+					usage.run(&self.pool, {
+						let img = img.clone();
+						let config = config.clone();
+						move || process(&img, &config, upload_sizes(&config))
+					}),
+					usage.run(&self.pool, {
+						let img = img.clone();
+						move || gen_placeholders(&img.image, &placeholder_config)
+					}),
+					async {
+						if with_svg {
+							usage
+								.run(&self.pool, move || {
+									gen_svg_placeholder(&img.image, &svg_config)
+								})
+								.await
+						} else {
+							Ok(String::new())
+						}
+					}
 		);
-		let response_buffers = response_buffers.map_err(|_| Status::internal(" Compression failed"))?;
+		let response_buffers = response_buffers??;
 		let aspect_ratio = response_buffers[0].aspect_ratio.clone();
 		let file_extension = response_buffers[0].file_extension.clone();
-		let media_meta = write_response_buffers(response_buffers, file_name).await.map_err(|_| Status::internal("FS failed"))?;
-		let blur_hash = blur_hash.map_err(|_| Status::internal("Something went wrong"))?;
+		let placeholders = placeholders?;
+		let svg_placeholder = svg_placeholder?;
+		let mut media_meta = write_response_buffers(
+			response_buffers,
+			file_name.clone(),
+			private,
+			config,
+			usage,
+		)
+		.await?;
+		let mut urls_expire_at = 0;
+		if private {
+			let signing = &config.http.signing;
+			let (prefix, ttl) = (tenant.url_prefix(), signing.ttl_secs);
+			for meta in &mut media_meta {
+				let size = Size::from_i32(meta.size).unwrap_or(Size::Original);
+				let signed = sign_url(signing, &prefix, &file_name, size, ttl)?;
+				meta.signed_url = signed.url;
+				urls_expire_at = signed.expires_at;
+			}
+		}
+		let placeholder_data_uri = if inline {
+			inline_placeholder(&placeholders, &config.placeholder)
+				.map_err(|_| MediaError::Placeholder)?
+		} else {
+			String::new()
+		};
 		let response = UploadAndWriteResponse {
 			aspect_ratio,
 			file_extension,
 			media_meta,
-			blur_hash,
+			blur_hash: placeholders.blur_hash,
+			thumb_hash: placeholders.thumb_hash,
+			placeholder_data_uri,
+			svg_placeholder,
+			urls_expire_at,
 		};
-		Ok(Response::new(response))
+		let mut response = Response::new(response);
+		warn_quota(&mut response, usage);
+		Ok(response)
+	}
+
+	async fn decode_placeholder(
+		&self,
+		request: Request<DecodePlaceholderRequest>,
+	) -> Result<Response<DecodePlaceholderResponse>, Status> {
+		let req = request.into_inner();
+		let invalid = |msg: &str| MediaError::InvalidArgument(msg.to_string());
+		let format = MimeType::from_i32(req.format)
+			.ok_or_else(|| invalid("Unknown format"))?;
+		let hash = req
+			.hash
+			.ok_or_else(|| invalid("Missing placeholder hash"))?;
+		let pixels = decode_placeholder(&hash, req.width, req.height)
+			.map_err(|_| invalid("Malformed placeholder hash"))?;
+		let buffer = encode_placeholder(&pixels, format)
+			.map_err(|_| invalid("Placeholders are rendered as PNG or WEBP"))?;
+		let (image, data_uri) = if req.data_uri {
+			(Vec::new(), to_data_uri(&buffer, format))
+		} else {
+			(buffer, String::new())
+		};
+		Ok(Response::new(DecodePlaceholderResponse {
+			image,
+			data_uri,
+			width: pixels.width(),
+			height: pixels.height(),
+			mimetype: format.into(),
+		}))
+	}
+
+	type ReprocessStream = mpsc::Receiver<Result<ReprocessProgress, Status>>;
+
+	async fn reprocess(
+		&self,
+		request: Request<ReprocessRequest>,
+	) -> Result<Response<Self::ReprocessStream>, Status> {
+		let principal = Principal::of(&request);
+		// rewrites a whole store, only for callers not bound to a tenant
+		if principal.tenant.is_some() {
+			let msg = format!("{} can't reprocess", principal);
+			return Err(MediaError::PermissionDenied(msg).into());
+		}
+		self.shedder.admit()?;
+		let tenant = self.tenants.of(&request)?;
+		let req = request.into_inner();
+		info!("{} started reprocessing (dry run: {})", principal, req.dry_run);
+		let throttle_ms = if req.throttle_ms == 0 {
+			tenant.config.reprocess.throttle_ms
+		} else {
+			req.throttle_ms
+		};
+		let (tx, rx) = mpsc::channel(4);
+		tokio::spawn(reprocess::run(
+			tenant.config.clone(),
+			self.pool.clone(),
+			tenant.usage.clone(),
+			req.dry_run,
+			Duration::from_millis(throttle_ms),
+			tx,
+		));
+		Ok(Response::new(rx))
+	}
+
+	async fn get(
+		&self,
+		request: Request<GetRequest>,
+	) -> Result<Response<GetResponse>, Status> {
+		let tenant = self.tenants.of(&request)?;
+		let req = request.into_inner();
+		let size = Size::from_i32(req.size)
+			.ok_or_else(|| MediaError::InvalidArgument("Unknown size".to_string()))?;
+		let stored = tenant.variants.get(&req.id, size, &self.shedder).await?;
+		Ok(Response::new(GetResponse {
+			byte_length: stored.buffer.len() as u64,
+			buffer: stored.buffer,
+			file_extension: stored.extension,
+			mime_type: stored.mime_type.to_string(),
+			sha256: stored.sha256,
+			generated: stored.generated,
+		}))
 	}
-}
 
-fn create_image_path(file_name: &str, size: Size) -> path::PathBuf {
-	format!("/images/{}_{}.jpeg", file_name, size.to_string()).into()
+	async fn sign_url(
+		&self,
+		request: Request<SignUrlRequest>,
+	) -> Result<Response<SignUrlResponse>, Status> {
+		let principal = Principal::of(&request);
+		let tenant = self.tenants.of(&request)?;
+		let req = request.into_inner();
+		debug!("{} signs a URL for {}", principal, req.id);
+		check_id(&req.id)?;
+		let signing = &tenant.config.http.signing;
+		let ttl_secs = if req.ttl_secs == 0 {
+			signing.ttl_secs
+		} else {
+			req.ttl_secs
+		};
+		let prefix = tenant.url_prefix();
+		let signed = if req.transform.is_empty() {
+			let size = Size::from_i32(req.size).ok_or_else(|| {
+				MediaError::InvalidArgument("Unknown size".to_string())
+			})?;
+			sign_url(signing, &prefix, &req.id, size, ttl_secs)?
+		} else {
+			if !tenant.config.http.transform.enabled {
+				return Err(MediaError::InvalidArgument(
+					"/img transformations are disabled".to_string(),
+				)
+				.into());
+			}
+			let params = req
+				.transform
+				.iter()
+				.map(|(name, value)| (name.as_str(), value.as_str()))
+				.collect();
+			sign_transform_url(signing, &prefix, &req.id, &params, ttl_secs)?
+		};
+		Ok(Response::new(SignUrlResponse {
+			url: signed.url,
+			expires_at: signed.expires_at,
+		}))
+	}
+
+	async fn get_usage(
+		&self,
+		request: Request<GetUsageRequest>,
+	) -> Result<Response<GetUsageResponse>, Status> {
+		let principal = Principal::of(&request);
+		let tenant = self.tenants.of(&request)?;
+		let req = request.into_inner();
+		let tenants = if req.all_tenants {
+			if principal.tenant.is_some() {
+				let msg = format!("{} can only see its own tenant", principal);
+				return Err(MediaError::PermissionDenied(msg).into());
+			}
+			self.tenants.all()
+		} else {
+			vec![tenant]
+		};
+		let tenants = tenants
+			.iter()
+			.map(|tenant| {
+				let usage = tenant.usage.usage();
+				let quota = tenant.usage.quota();
+				TenantUsage {
+					tenant: tenant.name.clone(),
+					bytes: usage.bytes,
+					objects: usage.objects,
+					cpu_seconds: usage.cpu_seconds,
+					soft_bytes: quota.soft_bytes,
+					hard_bytes: quota.hard_bytes,
+					soft_objects: quota.soft_objects,
+					hard_objects: quota.hard_objects,
+					soft_cpu_seconds: quota.soft_cpu_seconds,
+					hard_cpu_seconds: quota.hard_cpu_seconds,
+					over_soft_limit: tenant
+						.usage
+						.warnings()
+						.into_iter()
+						.map(str::to_string)
+						.collect(),
+				}
+			})
+			.collect();
+		Ok(Response::new(GetUsageResponse { tenants }))
+	}
 }
 
-fn create_file_path(file_name: &str, ext: &str) -> path::PathBuf {
-	format!("/files/{}.{}", file_name, ext.to_string()).into()
+/// Lists the resources the tenant uses beyond their soft quota in the
+/// response metadata.
+fn warn_quota<T>(response: &mut Response<T>, usage: &Meter) {
+	let warnings = usage.warnings();
+	if warnings.is_empty() {
+		return;
+	}
+	if let Ok(value) = warnings.join(",").parse() {
+		response.metadata_mut().insert(QUOTA_WARNING, value);
+	}
 }
 
+/// Where `UploadFile` stores `file_name.ext`, neither can climb out of the
+/// files directory.
+fn create_file_path(
+	storage: &StorageConfig,
+	file_name: &str,
+	ext: &str,
+) -> Result<path::PathBuf, MediaError> {
+	let valid_ext = !ext.contains(|c| matches!(c, '/' | '\\' | '\0'));
+	if !is_valid_id(file_name) || !valid_ext {
+		let msg = format!("invalid file name {}.{}", file_name, ext);
+		return Err(MediaError::InvalidArgument(msg));
+	}
+	Ok(storage.files_dir.join(format!("{}.{}", file_name, ext)))
+}
 
-const SIZE: [Size; 4] = [Size::Medium, Size::Placeholder, Size::Small, Size::Thumbnail];
 
-async fn process(image: DynamicImage) -> anyhow::Result<Vec<UploadResponse>, ()> {
-	let mut images: Vec<JoinHandle<UploadResponse>> = Vec::with_capacity(5);
-	let aspect_ratio = image.width() / image.height(); // 16:9
+/// Resized variants, largest first so each one can be derived from the
+/// previous.
+pub(super) const SIZE: [Size; 4] = [Size::Medium, Size::Small, Size::Thumbnail, Size::Placeholder];
 
-	for size in SIZE.iter() {
-		let image = image.clone();
-		let aspect_ratio = aspect_ratio.clone();
-		images.push(tokio::spawn(async move {
-			let dim = match size {
-				Size::Placeholder => { 64 }
-				Size::Thumbnail => { 200 }
-				Size::Small => { 400 }
-				Size::Medium => { 800 }
-				_ => {
-					unreachable!()
-				}
-			};
-			let image = image.thumbnail(dim, dim);
-			UploadResponse {
-				size: size.clone().into(),
-				buffer: get_image_bytes(&image),
-				file_extension: "jpeg".to_string(),
-				aspect_ratio: aspect_ratio.to_string(),
-				width: image.width().into(),
-				height: image.height().into(),
-				url_suffix: size.to_string(),
-			}
-		}))
+/// Variants produced at upload, besides the original.
+const fn upload_sizes(config: &Config) -> &'static [Size] {
+	match config.encoding.generation {
+		Generation::Eager => &SIZE,
+		Generation::Lazy => &[Size::Placeholder],
 	}
-	let mut images: Vec<_> = futures::future::join_all(images).await.into_iter().flatten().collect();
+}
+
+/// Decodes an uploaded buffer on the worker pool, the image is shared between
+/// the jobs that resize, encode and hash it. Originals that are stored as
+/// uploaded only need pixels for the largest variant, which lets JPEGs use a
+/// scaled decode. The time it takes is charged to `usage`.
+pub(super) async fn decode_image(
+	pool: &WorkerPool,
+	usage: &Arc<Meter>,
+	buffer: Vec<u8>,
+	mode: OriginalMode,
+) -> Result<Arc<Source>, MediaError> {
+	let max_dim = match mode {
+		OriginalMode::Reencode => None,
+		OriginalMode::Passthrough => {
+			SIZE.iter().map(|size| size_dimension(*size)).max()
+		},
+	};
+	usage
+		.run(pool, move || decode(buffer, max_dim))
+		.await?
+		.map(Arc::new)
+		.map_err(MediaError::Decode)
+}
+
+/// Resizes and encodes the original and the `sizes` variants, this is
+/// blocking work and is meant to run on the [`WorkerPool`].
+pub(super) fn process(
+	source: &Source,
+	config: &Config,
+	sizes: &[Size],
+) -> Result<Vec<UploadResponse>, MediaError> {
+	let image = &source.image;
+	let aspect_ratio = source.width / source.height; // 16:9
+	let (original, file_extension) = match &source.original {
+		Some(original) => (
+			Encoded {
+				buffer: original.clone(),
+				format: OutputFormat::Jpeg,
+				quality: 0,
+				score: 0.0,
+				unoptimized_size: 0,
+			},
+			source.extension().to_string(),
+		),
+		None => {
+			let encoded = encode(image, Size::Original, config).map_err(
+				|source| MediaError::Encode {
+					size: Size::Original,
+					source,
+				},
+			)?;
+			let ext = encoded.format.extension().to_string();
+			(encoded, ext)
+		},
+	};
 	let mut results = vec![
 		UploadResponse {
 			size: Size::Original.into(),
-			buffer: get_image_bytes(&image),
-			file_extension: "jpeg".to_string(),
+			byte_length: original.buffer.len() as u64,
+			sha256: sha256_hex(&original.buffer),
+			mime_type: mime_type(&file_extension).to_string(),
+			format: file_extension.clone(),
+			buffer: original.buffer,
+			file_extension,
 			aspect_ratio: aspect_ratio.to_string(),
-			width: image.width().into(),
-			height: image.height().into(),
+			width: source.width,
+			height: source.height,
 			url_suffix: Size::Original.to_string(),
+			quality: original.quality.into(),
+			score: original.score,
+			unoptimized_size: original.unoptimized_size as u64,
 		},
 	];
-	results.append(&mut images);
+	for (size, image) in cascade(image, sizes) {
+		let encoded = encode(&image, size, config)
+			.map_err(|source| MediaError::Encode { size, source })?;
+		let ext = encoded.format.extension();
+		results.push(UploadResponse {
+			size: size.into(),
+			byte_length: encoded.buffer.len() as u64,
+			sha256: sha256_hex(&encoded.buffer),
+			mime_type: mime_type(ext).to_string(),
+			format: ext.to_string(),
+			file_extension: ext.to_string(),
+			buffer: encoded.buffer,
+			aspect_ratio: aspect_ratio.to_string(),
+			width: image.width(),
+			height: image.height(),
+			url_suffix: size.to_string(),
+			quality: encoded.quality.into(),
+			score: encoded.score,
+			unoptimized_size: encoded.unoptimized_size as u64,
+		});
+	}
 	Ok(results)
 }
 
-fn get_image_bytes(image: &DynamicImage) -> Vec<u8> {
-	let mut output = Vec::new();
-	let mut j = JpegEncoder::new_with_quality(&mut output, 20);
-	j.encode(
-		&image.to_bytes(),
-		image.width(),
-		image.height(),
-		image.color(),
-	)
-		.unwrap();
-	output
-}
-
 // fn get_aspect_ratio(width: i32, height: i32) -> String {
 //     // 600/450 =>
 // }
 
-impl ToString for Size {
-	fn to_string(&self) -> String {
-		match self {
-			Size::Small => "sm-400".to_string(),
-			Size::Thumbnail => "th-200".to_string(),
-			Size::Placeholder => "th-20".to_string(),
-			Size::Medium => "md".to_string(),
-			Size::Original => "org".to_string(),
-		}
+impl fmt::Display for Size {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Size::Small => "sm-400",
+			Size::Thumbnail => "th-200",
+			Size::Placeholder => "th-20",
+			Size::Medium => "md",
+			Size::Original => "org",
+		})
 	}
 }
 
-pub async fn gen_blur_hash(img: DynamicImage) -> Result<String, ()> {
-	let (width, height) = img.dimensions();
-	Ok(blurhash::encode(4, 3, width, height, &img.to_rgba8().into_vec()))
+/// Longest edge, in pixels, of every resized variant.
+pub const fn size_dimension(size: Size) -> u32 {
+	match size {
+		Size::Placeholder => 64,
+		Size::Thumbnail => 200,
+		Size::Small => 400,
+		Size::Medium => 800,
+		Size::Original => u32::MAX,
+	}
 }
 
-pub async fn write_response_buffers(res_bufs: Vec<UploadResponse>, file_name: String) -> Result<Vec<MediaSize>, Status> {
-	let mut media_meta: Vec<JoinHandle<Result<MediaSize, Status>>> =
-		Vec::with_capacity(res_bufs.capacity());
+/// Writes every variant and describes them. The variants are committed
+/// together, so a media set is either stored completely or not at all, and
+/// charged to `usage`.
+pub async fn write_response_buffers(
+	res_bufs: Vec<UploadResponse>,
+	file_name: String,
+	private: bool,
+	config: &Config,
+	usage: &Arc<Meter>,
+) -> Result<Vec<MediaSize>, MediaError> {
+	// `file_name` is joined onto the images directory below
+	check_id(&file_name)?;
+	let storage = &config.storage;
+	let mut manifest = Manifest {
+		private,
+		..Manifest::default()
+	};
+	manifest.record(&res_bufs, config);
+	let mut files = Vec::with_capacity(res_bufs.len() + 1);
+	let mut media_meta = Vec::with_capacity(res_bufs.len());
 	for res_slice_buffer in res_bufs {
-		let file_name = file_name.clone();
-		media_meta.push(tokio::spawn(async move {
-			let image_size = Size::from_i32(res_slice_buffer.size).unwrap();
-			let file_path = create_image_path(&file_name.as_str(), image_size);
-			dbg!(&file_path);
-			let mut image_file = tokio::fs::File::create(file_path).await.map_err(|e| Status::internal(e.to_string()))?;
-			image_file
-				.write_all(&res_slice_buffer.buffer)
-				.map_err(|e| Status::internal(e.to_string())).await.unwrap();
-			image_file
-				.flush()
-				.map_err(|e| Status::internal(e.to_string())).await.unwrap();
-			dbg!(res_slice_buffer.size);
-			Ok(MediaSize {
-				height: res_slice_buffer.height,
-				width: res_slice_buffer.width,
-				size: res_slice_buffer.size,
-				url_suffix: res_slice_buffer.url_suffix,
-			})
-		}))
+		let image_size = Size::from_i32(res_slice_buffer.size).unwrap_or(Size::Original);
+		let path = image_path(storage, &file_name, image_size, &res_slice_buffer.file_extension);
+		debug!("writing {}", path.display());
+		files.push(Pending {
+			path,
+			buffer: res_slice_buffer.buffer,
+		});
+		media_meta.push(MediaSize {
+			height: res_slice_buffer.height,
+			width: res_slice_buffer.width,
+			size: res_slice_buffer.size,
+			url_suffix: res_slice_buffer.url_suffix,
+			quality: res_slice_buffer.quality,
+			score: res_slice_buffer.score,
+			unoptimized_size: res_slice_buffer.unoptimized_size,
+			byte_length: res_slice_buffer.byte_length,
+			sha256: res_slice_buffer.sha256,
+			mime_type: res_slice_buffer.mime_type,
+			format: res_slice_buffer.format,
+			signed_url: String::new(),
+		});
 	}
-	let images: Vec<_> = futures::future::join_all(media_meta).await.into_iter().flatten().flatten().collect();
-	Ok(images)
+	files.push(manifest.to_pending(&manifest_path(storage, &file_name)));
+	let usage = usage.clone();
+	tokio::task::spawn_blocking(move || usage.commit(files))
+		.await
+		.map_err(|_| MediaError::WorkerFailed)??;
+	Ok(media_meta)
 }
@@ -0,0 +1,30 @@
+use sha2::{Digest, Sha256};
+
+/// Hex encoded SHA-256 of a stored object, lets clients verify downloads.
+pub fn sha256_hex(buffer: &[u8]) -> String {
+	hex::encode(Sha256::digest(buffer))
+}
+
+/// Content type for a stored object, from its extension.
+pub fn mime_type(ext: &str) -> &'static str {
+	match ext.to_ascii_lowercase().as_str() {
+		"jpeg" | "jpg" => "image/jpeg",
+		"png" => "image/png",
+		"gif" => "image/gif",
+		"webp" => "image/webp",
+		"avif" => "image/avif",
+		"bmp" => "image/bmp",
+		"tif" | "tiff" => "image/tiff",
+		"ico" => "image/x-icon",
+		"svg" => "image/svg+xml",
+		"pdf" => "application/pdf",
+		"json" => "application/json",
+		"txt" => "text/plain",
+		"csv" => "text/csv",
+		"mp4" => "video/mp4",
+		"webm" => "video/webm",
+		"mp3" => "audio/mpeg",
+		"zip" => "application/zip",
+		_ => "application/octet-stream",
+	}
+}
@@ -1,2 +1,28 @@
+pub mod auth;
+mod decode;
+mod encode;
+pub mod error;
+pub mod health;
+pub mod layout;
+pub mod limit;
+mod manifest;
 mod media;
+pub mod meta;
+mod optimize;
+mod placeholder;
+mod pool;
+pub mod reflection;
+pub mod reprocess;
+mod resize;
+pub mod signing;
+mod sqip;
+mod ssim;
+pub mod storage;
+pub mod tenant;
+pub mod transform;
+pub mod usage;
+mod variants;
 pub use media::*;
+pub use pool::WorkerPool;
+pub use tenant::{Tenant, Tenants};
+pub use layout::is_valid_id;
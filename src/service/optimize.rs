@@ -0,0 +1,86 @@
+use image::ImageFormat;
+
+use crate::config::PngConfig;
+
+/// Result of running a PNG through [`optimize_png`].
+#[derive(Debug, Clone)]
+pub struct Optimized {
+	pub buffer: Vec<u8>,
+	/// Size of the PNG before optimizing it.
+	pub original_size: usize,
+}
+
+/// Shrinks a PNG: optionally quantizes it to a dithered palette first (lossy,
+/// meant for graphics and screenshots, needs the `quantize` feature), then searches filters and deflate
+/// settings losslessly. The input is returned untouched whenever the result
+/// would not be smaller.
+pub fn optimize_png(buffer: Vec<u8>, config: &PngConfig) -> Optimized {
+	let original_size = buffer.len();
+	let mut best = buffer;
+	#[cfg(feature = "quantize")]
+	if config.quantize {
+		if let Some(quantized) = quantize(&best, config) {
+			if quantized.len() < best.len() {
+				best = quantized;
+			}
+		}
+	}
+	if config.optimize {
+		let options = oxipng::Options::from_preset(config.level.min(6));
+		if let Ok(recompressed) = oxipng::optimize_from_memory(&best, &options) {
+			if recompressed.len() < best.len() {
+				best = recompressed;
+			}
+		}
+	}
+	Optimized {
+		buffer: best,
+		original_size,
+	}
+}
+
+/// Whether a stored file should go through [`optimize_png`].
+pub fn is_png(ext: &str, buffer: &[u8]) -> bool {
+	ext.eq_ignore_ascii_case("png")
+		&& matches!(image::guess_format(buffer), Ok(ImageFormat::Png))
+}
+
+#[cfg(feature = "quantize")]
+fn quantize(buffer: &[u8], config: &PngConfig) -> Option<Vec<u8>> {
+	use image::{DynamicImage, GenericImageView};
+	use rgb::FromSlice;
+
+	let image: DynamicImage =
+		image::load_from_memory_with_format(buffer, ImageFormat::Png).ok()?;
+	let (width, height) = image.dimensions();
+	let rgba = image.to_rgba8();
+
+	let mut liq = imagequant::new();
+	liq.set_speed(i32::from(config.speed.max(1).min(10))).ok()?;
+	liq.set_quality(config.quality_min, config.quality_max).ok()?;
+	let mut img = liq
+		.new_image_borrowed(
+			rgba.as_raw().as_rgba(),
+			width as usize,
+			height as usize,
+			0.0,
+		)
+		.ok()?;
+	let mut res = liq.quantize(&mut img).ok()?;
+	res.set_dithering_level(config.dithering).ok()?;
+	let (palette, pixels) = res.remapped(&mut img).ok()?;
+
+	let mut output = Vec::new();
+	{
+		let mut encoder = png::Encoder::new(&mut output, width, height);
+		encoder.set_color(png::ColorType::Indexed);
+		encoder.set_depth(png::BitDepth::Eight);
+		encoder.set_palette(
+			palette.iter().flat_map(|c| vec![c.r, c.g, c.b]).collect(),
+		);
+		encoder.set_trns(palette.iter().map(|c| c.a).collect());
+		let mut writer = encoder.write_header().ok()?;
+		writer.write_image_data(&pixels).ok()?;
+	}
+	Some(output)
+}
@@ -0,0 +1,193 @@
+use image::{
+	codecs::png::PngEncoder, imageops, ColorType, DynamicImage,
+	GenericImageView, RgbaImage,
+};
+
+use super::{resize::thumbnail, size_dimension};
+use crate::{
+	config::PlaceholderConfig,
+	pb::atwany::media::{decode_placeholder_request::Hash, MimeType, Size},
+};
+
+/// BlurHash accepts between 1 and 9 components per axis.
+const MAX_COMPONENTS: u32 = 9;
+/// Edge length used when the caller does not ask for a specific size.
+const DEFAULT_DECODE_DIM: u32 = 32;
+/// Anything larger defeats the point of a placeholder.
+const MAX_DECODE_DIM: u32 = 256;
+const BASE83: &[u8] =
+	b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+#[derive(Debug, Default, Clone)]
+pub struct Placeholders {
+	pub blur_hash: String,
+	pub thumb_hash: Vec<u8>,
+}
+
+/// Builds every enabled placeholder from the placeholder-sized variant rather
+/// than the full resolution image, hashing a 64px buffer costs next to nothing.
+pub fn gen_placeholders(
+	img: &DynamicImage,
+	config: &PlaceholderConfig,
+) -> Placeholders {
+	let dim = size_dimension(Size::Placeholder);
+	let small = thumbnail(img, dim);
+	let mut placeholders = Placeholders::default();
+	if config.blur_hash {
+		placeholders.blur_hash = gen_blur_hash(&small, config);
+	}
+	if config.thumb_hash {
+		placeholders.thumb_hash = gen_thumb_hash(&small);
+	}
+	placeholders
+}
+
+pub fn gen_blur_hash(img: &DynamicImage, config: &PlaceholderConfig) -> String {
+	let (width, height) = img.dimensions();
+	let (x, y) = blur_hash_components(width, height, config);
+	blurhash::encode(x, y, width, height, &img.to_rgba8().into_vec())
+}
+
+/// ThumbHash keeps the alpha channel and encodes the aspect ratio, the input
+/// must not exceed 100x100 which the placeholder variant never does.
+pub fn gen_thumb_hash(img: &DynamicImage) -> Vec<u8> {
+	let (width, height) = img.dimensions();
+	thumbhash::rgba_to_thumb_hash(
+		width as usize,
+		height as usize,
+		&img.to_rgba8().into_vec(),
+	)
+}
+
+fn blur_hash_components(
+	width: u32,
+	height: u32,
+	config: &PlaceholderConfig,
+) -> (u32, u32) {
+	let clamp = |c: u32| c.clamp(1, MAX_COMPONENTS);
+	if !config.aspect_aware || width == 0 || height == 0 {
+		return (clamp(config.x_components), clamp(config.y_components));
+	}
+	let long = clamp(config.x_components.max(config.y_components));
+	let short = |a: u32, b: u32| {
+		clamp((long as f32 * a as f32 / b as f32).round() as u32)
+	};
+	if width >= height {
+		(long, short(height, width))
+	} else {
+		(short(width, height), long)
+	}
+}
+
+/// Renders a BlurHash or ThumbHash back into pixels. A zero `width` or
+/// `height` is derived from the other one (and from the aspect ratio stored in
+/// a ThumbHash), falling back to a 32px square.
+pub fn decode_placeholder(
+	hash: &Hash,
+	width: u32,
+	height: u32,
+) -> Result<RgbaImage, ()> {
+	match hash {
+		Hash::BlurHash(hash) => {
+			if !is_valid_blur_hash(hash) {
+				return Err(());
+			}
+			let (width, height) = match (width, height) {
+				(0, 0) => (DEFAULT_DECODE_DIM, DEFAULT_DECODE_DIM),
+				(0, h) => (h, h),
+				(w, 0) => (w, w),
+				(w, h) => (w, h),
+			};
+			let (width, height) =
+				(width.min(MAX_DECODE_DIM), height.min(MAX_DECODE_DIM));
+			let pixels = blurhash::decode(hash, width, height, 1.0);
+			RgbaImage::from_raw(width, height, pixels).ok_or(())
+		},
+		Hash::ThumbHash(hash) => {
+			let (w, h, pixels) = thumbhash::thumb_hash_to_rgba(hash)?;
+			let image = RgbaImage::from_raw(w as u32, h as u32, pixels)
+				.ok_or(())?;
+			let (w, h) = image.dimensions();
+			let (width, height) = match (width, height) {
+				(0, 0) => return Ok(image),
+				(0, height) => (w * height / h, height),
+				(width, 0) => (width, h * width / w),
+				(width, height) => (width, height),
+			};
+			let (width, height) = (
+				width.clamp(1, MAX_DECODE_DIM),
+				height.clamp(1, MAX_DECODE_DIM),
+			);
+			Ok(imageops::resize(
+				&image,
+				width,
+				height,
+				imageops::FilterType::Triangle,
+			))
+		},
+	}
+}
+
+/// Encodes a decoded placeholder, only PNG and WebP make sense for these.
+pub fn encode_placeholder(
+	image: &RgbaImage,
+	format: MimeType,
+) -> Result<Vec<u8>, ()> {
+	let (width, height) = image.dimensions();
+	match format {
+		MimeType::Png => {
+			let mut output = Vec::new();
+			PngEncoder::new(&mut output)
+				.encode(image, width, height, ColorType::Rgba8)
+				.map_err(|_| ())?;
+			Ok(output)
+		},
+		MimeType::Webp => Ok(webp::Encoder::from_rgba(image, width, height)
+			.encode(80.0)
+			.to_vec()),
+		_ => Err(()),
+	}
+}
+
+pub fn to_data_uri(bytes: &[u8], format: MimeType) -> String {
+	let mime = match format {
+		MimeType::Png => "image/png",
+		MimeType::Jpeg => "image/jpeg",
+		MimeType::Gif => "image/gif",
+		MimeType::Webp => "image/webp",
+	};
+	format!("data:{};base64,{}", mime, base64::encode(bytes))
+}
+
+/// Inline data URI for the placeholders of a freshly uploaded image, ThumbHash
+/// is preferred since it keeps the aspect ratio and alpha.
+pub fn inline_placeholder(
+	placeholders: &Placeholders,
+	config: &PlaceholderConfig,
+) -> Result<String, ()> {
+	let hash = if !placeholders.thumb_hash.is_empty() {
+		Hash::ThumbHash(placeholders.thumb_hash.clone())
+	} else if !placeholders.blur_hash.is_empty() {
+		Hash::BlurHash(placeholders.blur_hash.clone())
+	} else {
+		return Err(());
+	};
+	let format = config.inline_format.into();
+	let image = decode_placeholder(&hash, config.inline_width, 0)?;
+	Ok(to_data_uri(&encode_placeholder(&image, format)?, format))
+}
+
+/// The upstream decoder panics on malformed input, so check the size flag
+/// against the length before handing it over.
+fn is_valid_blur_hash(hash: &str) -> bool {
+	let bytes = hash.as_bytes();
+	if bytes.len() < 6 || !bytes.iter().all(|b| BASE83.contains(b)) {
+		return false;
+	}
+	let flag = match BASE83.iter().position(|b| *b == bytes[0]) {
+		Some(flag) => flag,
+		None => return false,
+	};
+	let (x, y) = (flag % 9 + 1, flag / 9 + 1);
+	bytes.len() == 4 + 2 * x * y
+}
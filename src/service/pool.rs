@@ -0,0 +1,132 @@
+use std::{
+	sync::{
+		atomic::{AtomicU64, AtomicUsize, Ordering},
+		Arc,
+	},
+	time::Duration,
+};
+
+use log::info;
+use tokio::sync::Semaphore;
+
+use super::error::MediaError;
+use crate::config::PoolConfig;
+
+/// Runs CPU-bound image work (decoding, resizing, encoding, hashing) on the
+/// blocking thread pool, with at most `workers` jobs in flight and at most
+/// `max_queue` waiting for a slot. Anything beyond that is turned away with
+/// `ResourceExhausted` instead of piling up behind the gRPC reactor.
+#[derive(Debug)]
+pub struct WorkerPool {
+	permits: Arc<Semaphore>,
+	workers: usize,
+	max_queue: usize,
+	queued: AtomicUsize,
+	running: AtomicUsize,
+	completed: AtomicU64,
+	rejected: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PoolStats {
+	pub workers: usize,
+	pub max_queue: usize,
+	pub queued: usize,
+	pub running: usize,
+	pub completed: u64,
+	pub rejected: u64,
+}
+
+/// Keeps a counter incremented for as long as it lives, so cancelled requests
+/// don't leave the metrics skewed.
+struct Gauge<'a>(&'a AtomicUsize);
+
+impl<'a> Gauge<'a> {
+	fn new(counter: &'a AtomicUsize) -> Self {
+		counter.fetch_add(1, Ordering::SeqCst);
+		Self(counter)
+	}
+}
+
+impl Drop for Gauge<'_> {
+	fn drop(&mut self) { self.0.fetch_sub(1, Ordering::SeqCst); }
+}
+
+impl WorkerPool {
+	pub fn new(config: &PoolConfig) -> Self {
+		let workers = if config.workers == 0 {
+			num_cpus::get()
+		} else {
+			config.workers
+		};
+		Self {
+			permits: Arc::new(Semaphore::new(workers)),
+			workers,
+			max_queue: config.max_queue,
+			queued: AtomicUsize::new(0),
+			running: AtomicUsize::new(0),
+			completed: AtomicU64::new(0),
+			rejected: AtomicU64::new(0),
+		}
+	}
+
+	pub async fn run<F, T>(&self, job: F) -> Result<T, MediaError>
+	where
+		F: FnOnce() -> T + Send + 'static,
+		T: Send + 'static,
+	{
+		let permit = match self.permits.clone().try_acquire_owned() {
+			Ok(permit) => permit,
+			Err(_) => {
+				let queued = Gauge::new(&self.queued);
+				if self.queued.load(Ordering::SeqCst) > self.max_queue {
+					drop(queued);
+					self.rejected.fetch_add(1, Ordering::SeqCst);
+					return Err(MediaError::Overloaded);
+				}
+				let permit = self.permits.clone().acquire_owned().await;
+				drop(queued);
+				permit
+			},
+		};
+		let _running = Gauge::new(&self.running);
+		let result = tokio::task::spawn_blocking(move || {
+			let _permit = permit;
+			job()
+		})
+		.await
+		.map_err(|_| MediaError::WorkerFailed)?;
+		self.completed.fetch_add(1, Ordering::SeqCst);
+		Ok(result)
+	}
+
+	pub fn stats(&self) -> PoolStats {
+		PoolStats {
+			workers: self.workers,
+			max_queue: self.max_queue,
+			queued: self.queued.load(Ordering::SeqCst),
+			running: self.running.load(Ordering::SeqCst),
+			completed: self.completed.load(Ordering::SeqCst),
+			rejected: self.rejected.load(Ordering::SeqCst),
+		}
+	}
+
+	/// Logs the pool gauges every `interval` until the process exits.
+	pub async fn report(self: Arc<Self>, interval: Duration) {
+		let mut ticker = tokio::time::interval(interval);
+		loop {
+			ticker.tick().await;
+			let stats = self.stats();
+			info!(
+				"worker pool: running={}/{} queued={}/{} completed={} \
+				 rejected={}",
+				stats.running,
+				stats.workers,
+				stats.queued,
+				stats.max_queue,
+				stats.completed,
+				stats.rejected
+			);
+		}
+	}
+}
@@ -0,0 +1,216 @@
+use std::{collections::HashMap, sync::Arc};
+
+use futures::{channel::mpsc, SinkExt};
+use log::debug;
+use prost::Message;
+use prost_types::{DescriptorProto, FileDescriptorProto, FileDescriptorSet};
+use tonic::{Code, Request, Response, Status, Streaming};
+
+use crate::pb::{
+	reflection::{
+		server_reflection_request::MessageRequest,
+		server_reflection_response::MessageResponse,
+		server_reflection_server::ServerReflection, ErrorResponse,
+		FileDescriptorResponse, ListServiceResponse, ServerReflectionRequest,
+		ServerReflectionResponse, ServiceResponse,
+	},
+	FILE_DESCRIPTOR_SET,
+};
+pub use crate::pb::reflection::server_reflection_server::ServerReflectionServer;
+
+/// `grpc.reflection.v1alpha.ServerReflection`, describing the services from
+/// the descriptors compiled in by the build script.
+#[derive(Debug)]
+pub struct ReflectionService {
+	enabled: bool,
+	descriptors: Arc<Descriptors>,
+}
+
+#[derive(Debug)]
+struct Descriptors {
+	/// Every file by name, imports included.
+	files: HashMap<String, FileDescriptorProto>,
+	/// Fully-qualified services, methods, messages and enums, and the file
+	/// declaring them.
+	symbols: HashMap<String, String>,
+	services: Vec<String>,
+}
+
+impl ReflectionService {
+	/// Answers every request with `Unimplemented` unless `enabled`, like a
+	/// server without reflection would.
+	pub fn new(enabled: bool) -> Result<Self, prost::DecodeError> {
+		let set = FileDescriptorSet::decode(FILE_DESCRIPTOR_SET)?;
+		Ok(Self {
+			enabled,
+			descriptors: Arc::new(Descriptors::new(set)),
+		})
+	}
+}
+
+impl Descriptors {
+	fn new(set: FileDescriptorSet) -> Self {
+		let mut symbols = HashMap::new();
+		let mut services = Vec::new();
+		for file in &set.file {
+			let mut declare = |symbol: String| {
+				symbols.insert(symbol, file.name().to_string());
+			};
+			let package = file.package();
+			for service in &file.service {
+				let name = qualify(package, service.name());
+				for method in &service.method {
+					declare(qualify(&name, method.name()));
+				}
+				services.push(name.clone());
+				declare(name);
+			}
+			for message in &file.message_type {
+				declare_message(&mut declare, package, message);
+			}
+			for item in &file.enum_type {
+				declare(qualify(package, item.name()));
+			}
+		}
+		let files = set
+			.file
+			.into_iter()
+			.map(|file| (file.name().to_string(), file))
+			.collect();
+		Self {
+			files,
+			symbols,
+			services,
+		}
+	}
+
+	fn answer(&self, request: &MessageRequest) -> MessageResponse {
+		let file = match request {
+			MessageRequest::ListServices(_) => {
+				let service = self
+					.services
+					.iter()
+					.map(|name| ServiceResponse { name: name.clone() })
+					.collect();
+				return MessageResponse::ListServicesResponse(
+					ListServiceResponse { service },
+				);
+			},
+			MessageRequest::FileByFilename(name) => Some(name),
+			MessageRequest::FileContainingSymbol(symbol) => {
+				self.symbols.get(symbol.trim_start_matches('.'))
+			},
+			// the services declare no extensions
+			MessageRequest::FileContainingExtension(_)
+			| MessageRequest::AllExtensionNumbersOfType(_) => None,
+		};
+		match file.and_then(|name| self.with_dependencies(name)) {
+			Some(file_descriptor_proto) => {
+				let files = FileDescriptorResponse {
+					file_descriptor_proto,
+				};
+				MessageResponse::FileDescriptorResponse(files)
+			},
+			None => MessageResponse::ErrorResponse(ErrorResponse {
+				error_code: Code::NotFound as i32,
+				error_message: "not found".to_string(),
+			}),
+		}
+	}
+
+	/// The file called `name` followed by everything it imports, serialized.
+	fn with_dependencies(&self, name: &str) -> Option<Vec<Vec<u8>>> {
+		self.files.get(name)?;
+		let mut seen = vec![name];
+		let mut encoded = Vec::new();
+		let mut i = 0;
+		while let Some(&name) = seen.get(i) {
+			i += 1;
+			// imports are compiled in too, see build.rs
+			let file = match self.files.get(name) {
+				Some(file) => file,
+				None => continue,
+			};
+			let mut buffer = Vec::with_capacity(file.encoded_len());
+			// a Vec grows as needed, encoding into it can't fail
+			let _ = file.encode(&mut buffer);
+			encoded.push(buffer);
+			for dependency in &file.dependency {
+				if !seen.contains(&dependency.as_str()) {
+					seen.push(dependency.as_str());
+				}
+			}
+		}
+		Some(encoded)
+	}
+}
+
+fn declare_message(
+	declare: &mut impl FnMut(String),
+	scope: &str,
+	message: &DescriptorProto,
+) {
+	let name = qualify(scope, message.name());
+	for nested in &message.nested_type {
+		declare_message(declare, &name, nested);
+	}
+	for item in &message.enum_type {
+		declare(qualify(&name, item.name()));
+	}
+	declare(name);
+}
+
+fn qualify(scope: &str, name: &str) -> String {
+	if scope.is_empty() {
+		name.to_string()
+	} else {
+		format!("{}.{}", scope, name)
+	}
+}
+
+#[tonic::async_trait]
+impl ServerReflection for ReflectionService {
+	type ServerReflectionInfoStream =
+		mpsc::Receiver<Result<ServerReflectionResponse, Status>>;
+
+	async fn server_reflection_info(
+		&self,
+		request: Request<Streaming<ServerReflectionRequest>>,
+	) -> Result<Response<Self::ServerReflectionInfoStream>, Status> {
+		if !self.enabled {
+			return Err(Status::unimplemented("server reflection is disabled"));
+		}
+		let mut requests = request.into_inner();
+		let descriptors = self.descriptors.clone();
+		let (mut tx, rx) = mpsc::channel(1);
+		tokio::spawn(async move {
+			loop {
+				let request = match requests.message().await {
+					Ok(Some(request)) => request,
+					Ok(None) => return,
+					Err(status) => {
+						let _ = tx.send(Err(status)).await;
+						return;
+					},
+				};
+				let message_response = match &request.message_request {
+					Some(message) => descriptors.answer(message),
+					None => MessageResponse::ErrorResponse(ErrorResponse {
+						error_code: Code::InvalidArgument as i32,
+						error_message: "empty reflection request".to_string(),
+					}),
+				};
+				let response = ServerReflectionResponse {
+					valid_host: request.host.clone(),
+					original_request: Some(request),
+					message_response: Some(message_response),
+				};
+				if tx.send(Ok(response)).await.is_err() {
+					debug!("reflection stream closed by the client");
+					return;
+				}
+			}
+		});
+		Ok(Response::new(rx))
+	}
+}
@@ -0,0 +1,167 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use futures::{channel::mpsc, SinkExt};
+use log::{debug, warn};
+use tonic::Status;
+
+use super::{
+	error::MediaError,
+	layout::{image_path, manifest_path, originals},
+	manifest::{fingerprint, Manifest},
+	media::{decode_image, process, SIZE},
+	pool::WorkerPool,
+	storage::Pending,
+	usage::Meter,
+};
+use crate::{
+	config::{Config, Generation, OriginalMode},
+	pb::atwany::media::{ReprocessProgress, Size},
+};
+
+/// Walks every stored original and regenerates the variants that are missing
+/// or whose [`fingerprint`] no longer matches the config, one media at a time
+/// with a `throttle` pause in between so uploads keep the worker pool. Sends
+/// one progress message per media, and stops early when `tx` is dropped.
+/// What it stores and the processing it takes is charged to `usage`.
+pub async fn run(
+	config: Arc<Config>,
+	pool: Arc<WorkerPool>,
+	usage: Arc<Meter>,
+	dry_run: bool,
+	throttle: Duration,
+	mut tx: mpsc::Sender<Result<ReprocessProgress, Status>>,
+) {
+	let storage = config.storage.clone();
+	let listed = tokio::task::spawn_blocking(move || {
+		originals(&storage).map_err(|e| MediaError::storage(&storage.images_dir, e))
+	})
+	.await
+	.map_err(|_| MediaError::WorkerFailed);
+	let originals = match listed {
+		Ok(Ok(originals)) => originals,
+		Ok(Err(e)) | Err(e) => {
+			let _ = tx.send(Err(e.into())).await;
+			return;
+		},
+	};
+	let total = originals.len() as u64;
+	for (i, (id, original)) in originals.into_iter().enumerate() {
+		let mut progress = ReprocessProgress {
+			id: id.clone(),
+			processed: i as u64 + 1,
+			total,
+			..Default::default()
+		};
+		let reprocessed =
+			reprocess_one(&config, &pool, &usage, &id, original, dry_run).await;
+		match reprocessed {
+			Ok(regenerated) => {
+				progress.regenerated =
+					regenerated.into_iter().map(Into::into).collect()
+			},
+			Err(e) => {
+				warn!("failed to reprocess {}: {}", id, e);
+				progress.error = e.to_string();
+			},
+		}
+		if tx.send(Ok(progress)).await.is_err() {
+			debug!("reprocess stream closed, stopping");
+			return;
+		}
+		if throttle > Duration::from_millis(0) {
+			tokio::time::delay_for(throttle).await;
+		}
+	}
+}
+
+/// Regenerates the outdated variants of one media, returns which ones.
+async fn reprocess_one(
+	config: &Arc<Config>,
+	pool: &WorkerPool,
+	usage: &Arc<Meter>,
+	id: &str,
+	original: PathBuf,
+	dry_run: bool,
+) -> Result<Vec<Size>, MediaError> {
+	let storage = &config.storage;
+	let manifest_path = manifest_path(storage, id);
+	let mut manifest = {
+		let path = manifest_path.clone();
+		tokio::task::spawn_blocking(move || Manifest::load(&path))
+			.await
+			.map_err(|_| MediaError::WorkerFailed)??
+	};
+	let outdated: Vec<Size> = SIZE
+		.iter()
+		.copied()
+		.filter(|size| match manifest.get(*size) {
+			Some(entry) => {
+				entry.fingerprint != fingerprint(config, *size)
+					|| !image_path(storage, id, *size, &entry.extension)
+						.exists()
+			},
+			// lazily generated variants are rendered when first fetched
+			None => config.encoding.generation == Generation::Eager,
+		})
+		.collect();
+	if outdated.is_empty() || dry_run {
+		return Ok(outdated);
+	}
+
+	let buffer = tokio::fs::read(&original)
+		.await
+		.map_err(|e| MediaError::storage(&original, e))?;
+	// the original is never rewritten, only pixels for the largest variant
+	// are needed
+	let source =
+		decode_image(pool, usage, buffer, OriginalMode::Passthrough).await?;
+	let responses = {
+		let config = config.clone();
+		let sizes = outdated.clone();
+		usage
+			.run(pool, move || process(&source, &config, &sizes))
+			.await??
+	};
+	let responses: Vec<_> = responses
+		.into_iter()
+		.filter(|res| res.size != i32::from(Size::Original))
+		.collect();
+
+	let mut stale = Vec::new();
+	for res in &responses {
+		let size = Size::from_i32(res.size).unwrap_or(Size::Original);
+		if let Some(entry) = manifest.get(size) {
+			if entry.extension != res.file_extension {
+				stale.push(image_path(storage, id, size, &entry.extension));
+			}
+		}
+		// alternates of the old preset are rendered again when negotiated
+		for ext in manifest.remove_alternates(size) {
+			if ext != res.file_extension {
+				stale.push(image_path(storage, id, size, &ext));
+			}
+		}
+	}
+	manifest.record(&responses, config);
+	let mut files: Vec<_> = responses
+		.into_iter()
+		.map(|res| {
+			let size = Size::from_i32(res.size).unwrap_or(Size::Original);
+			Pending {
+				path: image_path(storage, id, size, &res.file_extension),
+				buffer: res.buffer,
+			}
+		})
+		.collect();
+	files.push(manifest.to_pending(&manifest_path));
+	let usage = usage.clone();
+	tokio::task::spawn_blocking(move || -> Result<(), MediaError> {
+		usage.commit(files)?;
+		// variants whose format changed leave the old file behind
+		usage.remove(&stale);
+		Ok(())
+	})
+	.await
+	.map_err(|_| MediaError::WorkerFailed)??;
+	Ok(outdated)
+}
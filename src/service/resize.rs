@@ -0,0 +1,143 @@
+use std::num::NonZeroU32;
+
+use fast_image_resize as fr;
+use image::{DynamicImage, GenericImageView, ImageBuffer};
+
+use super::size_dimension;
+use crate::pb::atwany::media::Size;
+
+/// Resizes `image` into every size in `sizes`, largest first, deriving each
+/// variant from the previous one (800 -> 400 -> 200 -> 64) instead of from
+/// the original. Only the source and the variant being produced are ever
+/// resampled, so memory stays close to a single full size buffer.
+pub fn cascade(image: &DynamicImage, sizes: &[Size]) -> Vec<(Size, DynamicImage)> {
+	let mut sizes = sizes.to_vec();
+	sizes.sort_by_key(|size| std::cmp::Reverse(size_dimension(*size)));
+	let mut variants: Vec<(Size, DynamicImage)> = Vec::with_capacity(sizes.len());
+	for size in sizes {
+		let (width, height) = fit(image.dimensions(), size_dimension(size));
+		// a smaller previous variant would mean upscaling a downscaled image
+		let source = match variants.last() {
+			Some((_, previous)) if previous.width() >= width => previous,
+			_ => image,
+		};
+		let variant = resize(source, width, height);
+		variants.push((size, variant));
+	}
+	variants
+}
+
+/// Same as [`DynamicImage::thumbnail`] but on the SIMD resizer.
+pub fn thumbnail(image: &DynamicImage, dim: u32) -> DynamicImage {
+	let (width, height) = fit(image.dimensions(), dim);
+	resize(image, width, height)
+}
+
+/// Dimensions of `(width, height)` scaled to fit a `dim` x `dim` box,
+/// preserving the aspect ratio like `image`'s `thumbnail` does.
+pub fn fit((width, height): (u32, u32), dim: u32) -> (u32, u32) {
+	let ratio = f64::min(
+		f64::from(dim) / f64::from(width),
+		f64::from(dim) / f64::from(height),
+	);
+	let scale = |v: u32| ((f64::from(v) * ratio).round() as u32).max(1);
+	(scale(width), scale(height))
+}
+
+pub fn resize(image: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+	if image.dimensions() == (width, height) {
+		return image.clone();
+	}
+	let converted;
+	let (buffer, pixel_type, rgb) = match image {
+		DynamicImage::ImageRgb8(rgb) => {
+			(rgb.as_raw().as_slice(), fr::PixelType::U8x3, true)
+		},
+		DynamicImage::ImageRgba8(rgba) => {
+			(rgba.as_raw().as_slice(), fr::PixelType::U8x4, false)
+		},
+		_ => {
+			converted = image.to_rgba8();
+			(converted.as_raw().as_slice(), fr::PixelType::U8x4, false)
+		},
+	};
+	let dimension = |v: u32| NonZeroU32::new(v.max(1)).unwrap();
+	let (src_width, src_height) = image.dimensions();
+	let resized = fr::ImageView::from_buffer(
+		dimension(src_width),
+		dimension(src_height),
+		buffer,
+		pixel_type,
+	)
+	.ok()
+	.and_then(|src| {
+		let mut dst =
+			fr::Image::new(dimension(width), dimension(height), pixel_type);
+		let mut resizer = fr::Resizer::new(fr::ResizeAlg::Convolution(
+			fr::FilterType::Lanczos3,
+		));
+		if rgb {
+			resizer.resize(&src, &mut dst.view_mut()).ok()?;
+			return Some(dst.into_vec());
+		}
+		// on straight alpha the color of transparent pixels would bleed into
+		// the edges as a halo
+		let mul_div = fr::MulDiv::default();
+		let mut premultiplied = fr::Image::new(
+			dimension(src_width),
+			dimension(src_height),
+			pixel_type,
+		);
+		mul_div.multiply_alpha(&src, &mut premultiplied.view_mut()).ok()?;
+		resizer.resize(&premultiplied.view(), &mut dst.view_mut()).ok()?;
+		mul_div.divide_alpha_inplace(&mut dst.view_mut()).ok()?;
+		Some(dst.into_vec())
+	});
+	let resized = match resized {
+		Some(resized) => resized,
+		// the buffer always matches the dimensions, but don't bet the request
+		// on it
+		None => return image.thumbnail_exact(width, height),
+	};
+	if rgb {
+		ImageBuffer::from_raw(width, height, resized)
+			.map(DynamicImage::ImageRgb8)
+			.unwrap_or_else(|| image.thumbnail_exact(width, height))
+	} else {
+		ImageBuffer::from_raw(width, height, resized)
+			.map(DynamicImage::ImageRgba8)
+			.unwrap_or_else(|| image.thumbnail_exact(width, height))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use image::{Rgba, RgbaImage};
+
+	use super::*;
+
+	#[test]
+	fn transparent_edges_keep_their_color() {
+		// opaque white on the left, transparent black on the right
+		let image = RgbaImage::from_fn(10, 10, |x, _| {
+			if x < 5 {
+				Rgba([255, 255, 255, 255])
+			} else {
+				Rgba([0, 0, 0, 0])
+			}
+		});
+		let resized = resize(&DynamicImage::ImageRgba8(image), 3, 3).to_rgba8();
+		let mut edges = 0;
+		for Rgba([r, g, b, a]) in resized.pixels() {
+			if *a == 0 {
+				continue;
+			}
+			if *a < 255 {
+				edges += 1;
+			}
+			let white = [r, g, b].iter().all(|channel| **channel >= 250);
+			assert!(white, "halo: {:?}", [r, g, b, a]);
+		}
+		assert!(edges > 0);
+	}
+}
@@ -0,0 +1,174 @@
+use std::{
+	collections::BTreeMap,
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+
+use super::error::MediaError;
+use crate::{config::SigningConfig, pb::atwany::media::Size};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The `/img` parameters a signed rendition URL may carry.
+const TRANSFORM_PARAMS: [&str; 5] = ["w", "h", "fit", "fmt", "q"];
+
+/// An expiring URL for a variant of private media.
+#[derive(Debug, Clone)]
+pub struct SignedUrl {
+	pub url: String,
+	/// Unix time.
+	pub expires_at: u64,
+}
+
+/// Signs the `/media` URL of the `size` variant of `id` with the first
+/// configured key, for `ttl_secs`. `prefix` is the tenant's, see
+/// [`Tenant::url_prefix`](super::tenant::Tenant::url_prefix).
+pub fn sign_url(
+	config: &SigningConfig,
+	prefix: &str,
+	id: &str,
+	size: Size,
+	ttl_secs: u64,
+) -> Result<SignedUrl, MediaError> {
+	let key = config.keys.first().ok_or(MediaError::SigningDisabled)?;
+	let expires_at = now().saturating_add(ttl_secs);
+	let path = format!("{}/media/{}/{}", prefix, id, size);
+	let signature = sign(&key.secret, &message(&path, expires_at));
+	let url = format!(
+		"{}{}/media/{}/{}?exp={}&kid={}&sig={}",
+		config.base_url.trim_end_matches('/'),
+		prefix,
+		percent_encode(id),
+		size,
+		expires_at,
+		percent_encode(&key.id),
+		signature,
+	);
+	Ok(SignedUrl { url, expires_at })
+}
+
+/// Signs the `/img` URL of the rendition of `id` that `params` describe,
+/// like [`sign_url`] does for its variants.
+pub fn sign_transform_url(
+	config: &SigningConfig,
+	prefix: &str,
+	id: &str,
+	params: &BTreeMap<&str, &str>,
+	ttl_secs: u64,
+) -> Result<SignedUrl, MediaError> {
+	for (name, value) in params {
+		// sent as they are, so they have to survive a query string
+		let plain = !value.is_empty()
+			&& value.bytes().all(|b| b.is_ascii_alphanumeric());
+		if !TRANSFORM_PARAMS.contains(name) || !plain {
+			return Err(MediaError::InvalidArgument(format!(
+				"invalid `{}` transform parameter",
+				name
+			)));
+		}
+	}
+	let key = config.keys.first().ok_or(MediaError::SigningDisabled)?;
+	let expires_at = now().saturating_add(ttl_secs);
+	let path = format!("{}/img/{}", prefix, id);
+	let signed = transform_message(&path, params);
+	let signature = sign(&key.secret, &message(&signed, expires_at));
+	let mut query: Vec<String> = params
+		.iter()
+		.map(|(name, value)| format!("{}={}", name, value))
+		.collect();
+	query.push(format!("exp={}", expires_at));
+	query.push(format!("kid={}", percent_encode(&key.id)));
+	query.push(format!("sig={}", signature));
+	let url = format!(
+		"{}{}/img/{}?{}",
+		config.base_url.trim_end_matches('/'),
+		prefix,
+		percent_encode(id),
+		query.join("&"),
+	);
+	Ok(SignedUrl { url, expires_at })
+}
+
+/// What the `/img` URL of a private rendition is signed over, in place of
+/// its path: the path followed by the parameters but `exp`, `kid` and `sig`,
+/// sorted by name.
+pub fn transform_message(path: &str, params: &BTreeMap<&str, &str>) -> String {
+	let signed: Vec<String> = params
+		.iter()
+		.filter(|(name, _)| !matches!(**name, "exp" | "kid" | "sig"))
+		.map(|(name, value)| format!("{}={}", name, value))
+		.collect();
+	format!("{}?{}", path, signed.join("&"))
+}
+
+/// Checks a signed URL. `path` is the percent-decoded path, `kid` picks the
+/// key, all of them are tried without it.
+pub fn verify_url(
+	config: &SigningConfig,
+	path: &str,
+	exp: &str,
+	kid: Option<&str>,
+	signature: &str,
+) -> bool {
+	let expires_at = match exp.parse::<u64>() {
+		Ok(expires_at) if expires_at >= now() => expires_at,
+		_ => return false,
+	};
+	let message = message(path, expires_at);
+	config
+		.keys
+		.iter()
+		.filter(|key| kid.map_or(true, |kid| kid == key.id))
+		.any(|key| verify(&key.secret, &message, signature))
+}
+
+/// Hex encoded HMAC-SHA256 of `message`.
+fn sign(key: &str, message: &str) -> String {
+	let mut mac = mac(key);
+	mac.update(message.as_bytes());
+	hex::encode(mac.finalize().into_bytes())
+}
+
+/// Checks the hex encoded HMAC-SHA256 `signature` of `message`, in constant
+/// time.
+pub fn verify(key: &str, message: &str, signature: &str) -> bool {
+	let signature = match hex::decode(signature) {
+		Ok(signature) => signature,
+		Err(_) => return false,
+	};
+	let mut mac = mac(key);
+	mac.update(message.as_bytes());
+	mac.verify(&signature).is_ok()
+}
+
+/// Seconds since the epoch.
+fn now() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_or(0, |since| since.as_secs())
+}
+
+fn message(path: &str, expires_at: u64) -> String {
+	format!("{}\n{}", path, expires_at)
+}
+
+fn mac(key: &str) -> HmacSha256 {
+	HmacSha256::new_varkey(key.as_bytes())
+		.unwrap_or_else(|_| unreachable!("HMAC accepts keys of any length"))
+}
+
+/// Escapes everything but unreserved characters, so ids survive as a single
+/// path segment.
+fn percent_encode(segment: &str) -> String {
+	segment
+		.bytes()
+		.map(|b| match b {
+			b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+				(b as char).to_string()
+			},
+			_ => format!("%{:02X}", b),
+		})
+		.collect()
+}
@@ -0,0 +1,325 @@
+//! SQIP style placeholders: the image is approximated by a handful of
+//! translucent triangles and ellipses which are then blurred by the browser,
+//! the result is a few hundred bytes of SVG.
+
+use std::{
+	fmt::Write,
+	time::{Duration, Instant},
+};
+
+use image::{imageops::FilterType, DynamicImage, RgbImage};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::config::{SvgPlaceholderConfig, SvgShape};
+
+/// Opacity every shape is drawn with, matches the `fill-opacity` of the group.
+const ALPHA: f64 = 0.5;
+/// Random candidates tried before hill climbing the best of them.
+const CANDIDATES: usize = 48;
+/// Mutations tried on the best candidate per shape.
+const MUTATIONS: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+enum Shape {
+	Triangle([(i32, i32); 3]),
+	Ellipse { cx: i32, cy: i32, rx: i32, ry: i32 },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+	shape: Shape,
+	color: [u8; 3],
+	delta: f64,
+}
+
+struct Canvas {
+	width: i32,
+	height: i32,
+	target: RgbImage,
+	current: Vec<[f64; 3]>,
+}
+
+pub fn gen_svg_placeholder(
+	img: &DynamicImage,
+	config: &SvgPlaceholderConfig,
+) -> String {
+	let dim = config.work_size.max(16);
+	let target = img.resize(dim, dim, FilterType::Triangle).to_rgb8();
+	let (width, height) = target.dimensions();
+	let background = average(&target);
+	let mut canvas = Canvas {
+		width: width as i32,
+		height: height as i32,
+		current: vec![
+			[
+				f64::from(background[0]),
+				f64::from(background[1]),
+				f64::from(background[2])
+			];
+			(width * height) as usize
+		],
+		target,
+	};
+	// a fixed seed keeps the output stable for the same input
+	let mut rng = StdRng::seed_from_u64(0x5147_4950);
+	let deadline =
+		Instant::now() + Duration::from_millis(config.time_budget_ms);
+	let mut shapes = Vec::with_capacity(config.shapes as usize);
+	for i in 0..config.shapes {
+		if Instant::now() >= deadline {
+			break;
+		}
+		let kind = match config.shape {
+			SvgShape::Mixed if i % 2 == 0 => SvgShape::Triangle,
+			SvgShape::Mixed => SvgShape::Ellipse,
+			kind => kind,
+		};
+		if let Some(best) = canvas.best_shape(kind, &mut rng, deadline) {
+			canvas.draw(&best);
+			shapes.push(best);
+		}
+	}
+	to_svg(width, height, background, &shapes, config.blur)
+}
+
+impl Canvas {
+	fn best_shape(
+		&self,
+		kind: SvgShape,
+		rng: &mut StdRng,
+		deadline: Instant,
+	) -> Option<Candidate> {
+		let mut best: Option<Candidate> = None;
+		for _ in 0..CANDIDATES {
+			let candidate = self.evaluate(self.random_shape(kind, rng));
+			if best.map_or(true, |b| candidate.delta < b.delta) {
+				best = Some(candidate);
+			}
+		}
+		let mut best = best?;
+		for _ in 0..MUTATIONS {
+			if Instant::now() >= deadline {
+				break;
+			}
+			let candidate = self.evaluate(self.mutate(best.shape, rng));
+			if candidate.delta < best.delta {
+				best = candidate;
+			}
+		}
+		if best.delta < 0.0 {
+			Some(best)
+		} else {
+			None
+		}
+	}
+
+	fn random_shape(&self, kind: SvgShape, rng: &mut StdRng) -> Shape {
+		let (w, h) = (self.width, self.height);
+		match kind {
+			SvgShape::Ellipse => Shape::Ellipse {
+				cx: rng.gen_range(0, w),
+				cy: rng.gen_range(0, h),
+				rx: rng.gen_range(1, (w / 2).max(2)),
+				ry: rng.gen_range(1, (h / 2).max(2)),
+			},
+			_ => {
+				let x = rng.gen_range(0, w);
+				let y = rng.gen_range(0, h);
+				let spread = (w.max(h) / 3).max(2);
+				let mut point = || {
+					(
+						x + rng.gen_range(-spread, spread),
+						y + rng.gen_range(-spread, spread),
+					)
+				};
+				Shape::Triangle([point(), point(), point()])
+			},
+		}
+	}
+
+	fn mutate(&self, shape: Shape, rng: &mut StdRng) -> Shape {
+		let step = (self.width.max(self.height) / 8).max(2);
+		let mut jitter = || rng.gen_range(-step, step + 1);
+		match shape {
+			Shape::Triangle(mut points) => {
+				for point in points.iter_mut() {
+					point.0 += jitter();
+					point.1 += jitter();
+				}
+				Shape::Triangle(points)
+			},
+			Shape::Ellipse { cx, cy, rx, ry } => Shape::Ellipse {
+				cx: cx + jitter(),
+				cy: cy + jitter(),
+				rx: (rx + jitter()).max(1),
+				ry: (ry + jitter()).max(1),
+			},
+		}
+	}
+
+	/// Picks the color that best fits the pixels under `shape` and returns
+	/// how much drawing it would change the total squared error.
+	fn evaluate(&self, shape: Shape) -> Candidate {
+		let spans = self.spans(shape);
+		let mut sum = [0.0f64; 3];
+		let mut count = 0usize;
+		self.each_pixel(&spans, |target, current| {
+			for c in 0..3 {
+				sum[c] += (target[c] - current[c] * (1.0 - ALPHA)) / ALPHA;
+			}
+			count += 1;
+		});
+		if count == 0 {
+			return Candidate {
+				shape,
+				color: [0; 3],
+				delta: 0.0,
+			};
+		}
+		let color = [
+			(sum[0] / count as f64).clamp(0.0, 255.0),
+			(sum[1] / count as f64).clamp(0.0, 255.0),
+			(sum[2] / count as f64).clamp(0.0, 255.0),
+		];
+		let mut delta = 0.0;
+		self.each_pixel(&spans, |target, current| {
+			for c in 0..3 {
+				let next = current[c] * (1.0 - ALPHA) + color[c] * ALPHA;
+				delta += (target[c] - next).powi(2)
+					- (target[c] - current[c]).powi(2);
+			}
+		});
+		Candidate {
+			shape,
+			color: [color[0] as u8, color[1] as u8, color[2] as u8],
+			delta,
+		}
+	}
+
+	fn draw(&mut self, candidate: &Candidate) {
+		let width = self.width;
+		for (y, x0, x1) in self.spans(candidate.shape) {
+			for x in x0..=x1 {
+				let pixel = &mut self.current[(y * width + x) as usize];
+				for (value, &color) in pixel.iter_mut().zip(&candidate.color) {
+					*value = *value * (1.0 - ALPHA) + f64::from(color) * ALPHA;
+				}
+			}
+		}
+	}
+
+	fn each_pixel(
+		&self,
+		spans: &[(i32, i32, i32)],
+		mut f: impl FnMut([f64; 3], [f64; 3]),
+	) {
+		for &(y, x0, x1) in spans {
+			for x in x0..=x1 {
+				let target = self.target.get_pixel(x as u32, y as u32).0;
+				let target = [
+					f64::from(target[0]),
+					f64::from(target[1]),
+					f64::from(target[2]),
+				];
+				f(target, self.current[(y * self.width + x) as usize]);
+			}
+		}
+	}
+
+	/// Horizontal runs `(y, x_start, x_end)` covered by `shape`, clipped to
+	/// the canvas.
+	fn spans(&self, shape: Shape) -> Vec<(i32, i32, i32)> {
+		let mut spans = Vec::new();
+		let (w, h) = (self.width, self.height);
+		let mut push = |y: i32, x0: i32, x1: i32| {
+			let (x0, x1) = (x0.max(0), x1.min(w - 1));
+			if y >= 0 && y < h && x0 <= x1 {
+				spans.push((y, x0, x1));
+			}
+		};
+		match shape {
+			Shape::Ellipse { cx, cy, rx, ry } => {
+				for y in (cy - ry)..=(cy + ry) {
+					let dy = f64::from(y - cy) / f64::from(ry);
+					let dx = (f64::from(rx) * (1.0 - dy * dy).max(0.0).sqrt())
+						as i32;
+					push(y, cx - dx, cx + dx);
+				}
+			},
+			Shape::Triangle(points) => {
+				let min_y = points.iter().map(|p| p.1).min().unwrap_or(0);
+				let max_y = points.iter().map(|p| p.1).max().unwrap_or(0);
+				for y in min_y..=max_y {
+					let mut xs = Vec::with_capacity(3);
+					for i in 0..3 {
+						let (a, b) = (points[i], points[(i + 1) % 3]);
+						if (a.1 <= y && y < b.1) || (b.1 <= y && y < a.1) {
+							let t = f64::from(y - a.1) / f64::from(b.1 - a.1);
+							xs.push(a.0 + (t * f64::from(b.0 - a.0)) as i32);
+						}
+					}
+					if let (Some(x0), Some(x1)) =
+						(xs.iter().min(), xs.iter().max())
+					{
+						push(y, *x0, *x1);
+					}
+				}
+			},
+		}
+		spans
+	}
+}
+
+fn average(image: &RgbImage) -> [u8; 3] {
+	let mut sum = [0u64; 3];
+	for pixel in image.pixels() {
+		for c in 0..3 {
+			sum[c] += u64::from(pixel[c]);
+		}
+	}
+	let count = u64::from(image.width() * image.height()).max(1);
+	[
+		(sum[0] / count) as u8,
+		(sum[1] / count) as u8,
+		(sum[2] / count) as u8,
+	]
+}
+
+fn to_svg(
+	width: u32,
+	height: u32,
+	background: [u8; 3],
+	shapes: &[Candidate],
+	blur: f32,
+) -> String {
+	let hex = |c: [u8; 3]| format!("#{:02x}{:02x}{:02x}", c[0], c[1], c[2]);
+	let mut svg = format!(
+		"<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {} {}\">\
+		 <filter id=\"b\"><feGaussianBlur stdDeviation=\"{}\"/></filter>\
+		 <rect width=\"100%\" height=\"100%\" fill=\"{}\"/>\
+		 <g filter=\"url(#b)\" fill-opacity=\"{}\">",
+		width,
+		height,
+		blur,
+		hex(background),
+		ALPHA
+	);
+	for candidate in shapes {
+		let fill = hex(candidate.color);
+		// writing into a String never fails
+		let _ = match candidate.shape {
+			Shape::Triangle(p) => write!(
+				svg,
+				"<polygon fill=\"{}\" points=\"{},{} {},{} {},{}\"/>",
+				fill, p[0].0, p[0].1, p[1].0, p[1].1, p[2].0, p[2].1
+			),
+			Shape::Ellipse { cx, cy, rx, ry } => write!(
+				svg,
+				"<ellipse fill=\"{}\" cx=\"{}\" cy=\"{}\" rx=\"{}\" ry=\"{}\"/>",
+				fill, cx, cy, rx, ry
+			),
+		};
+	}
+	svg.push_str("</g></svg>");
+	svg
+}
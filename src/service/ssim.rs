@@ -0,0 +1,63 @@
+use image::{DynamicImage, GrayImage};
+
+/// Side of the square windows the statistics are gathered over.
+const WINDOW: u32 = 8;
+const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+/// Mean structural similarity of the luma channels of two images of the same
+/// size, `1.0` meaning identical. Windows don't overlap, which is plenty to
+/// rank encoder qualities against each other and a lot cheaper.
+pub fn ssim(reference: &DynamicImage, distorted: &DynamicImage) -> f64 {
+	let a = reference.to_luma8();
+	let b = distorted.to_luma8();
+	if a.dimensions() != b.dimensions() {
+		return 0.0;
+	}
+	let (width, height) = a.dimensions();
+	if width < WINDOW || height < WINDOW {
+		return window_ssim(&a, &b, 0, 0, width, height);
+	}
+	let mut total = 0.0;
+	let mut windows = 0u32;
+	for y in (0..=height - WINDOW).step_by(WINDOW as usize) {
+		for x in (0..=width - WINDOW).step_by(WINDOW as usize) {
+			total += window_ssim(&a, &b, x, y, WINDOW, WINDOW);
+			windows += 1;
+		}
+	}
+	total / f64::from(windows)
+}
+
+fn window_ssim(
+	a: &GrayImage,
+	b: &GrayImage,
+	x0: u32,
+	y0: u32,
+	width: u32,
+	height: u32,
+) -> f64 {
+	let n = f64::from(width * height);
+	if n == 0.0 {
+		return 1.0;
+	}
+	let (mut sum_a, mut sum_b) = (0.0, 0.0);
+	let (mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0);
+	for y in y0..y0 + height {
+		for x in x0..x0 + width {
+			let pa = f64::from(a.get_pixel(x, y)[0]);
+			let pb = f64::from(b.get_pixel(x, y)[0]);
+			sum_a += pa;
+			sum_b += pb;
+			sum_aa += pa * pa;
+			sum_bb += pb * pb;
+			sum_ab += pa * pb;
+		}
+	}
+	let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+	let var_a = sum_aa / n - mean_a * mean_a;
+	let var_b = sum_bb / n - mean_b * mean_b;
+	let covariance = sum_ab / n - mean_a * mean_b;
+	((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+		/ ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2))
+}
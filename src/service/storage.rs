@@ -0,0 +1,177 @@
+use std::{
+	fs,
+	io::Write,
+	path::{Path, PathBuf},
+};
+
+use log::warn;
+
+use super::error::MediaError;
+
+/// Extension of the files a [`commit`] writes before renaming them.
+const TEMP: &str = "tmp";
+/// Extension of the files a [`commit`] replaces, until it is done.
+const BACKUP: &str = "bak";
+
+/// A file that is part of a [`commit`] batch.
+#[derive(Debug)]
+pub struct Pending {
+	pub path: PathBuf,
+	pub buffer: Vec<u8>,
+}
+
+/// A file that was moved into place, and whatever it replaced.
+struct Placed {
+	path: PathBuf,
+	backup: Option<PathBuf>,
+}
+
+/// Writes a batch of files so that either all of them end up in place or none
+/// of them do. Every file is first written and fsynced next to its target
+/// under a temporary name, then renamed over it, so readers always find
+/// either the old or the new file. Files that get replaced are kept aside
+/// until the whole batch is in place, so a failure half way through restores
+/// what was there before.
+///
+/// Blocking, run it with `spawn_blocking`.
+pub fn commit(files: Vec<Pending>) -> Result<(), MediaError> {
+	let mut staged: Vec<(PathBuf, PathBuf)> = Vec::with_capacity(files.len());
+	for file in files {
+		let temp = sibling(&file.path, TEMP);
+		if let Err(e) = write_synced(&temp, &file.buffer) {
+			remove(&temp);
+			for (temp, _) in &staged {
+				remove(temp);
+			}
+			return Err(MediaError::storage(&file.path, e));
+		}
+		staged.push((temp, file.path));
+	}
+
+	let mut placed: Vec<Placed> = Vec::with_capacity(staged.len());
+	for (i, (temp, path)) in staged.iter().enumerate() {
+		match place(temp, path) {
+			Ok(backup) => placed.push(Placed {
+				path: path.clone(),
+				backup,
+			}),
+			Err(e) => {
+				for (temp, _) in &staged[i..] {
+					remove(temp);
+				}
+				rollback(placed);
+				return Err(MediaError::storage(path, e));
+			},
+		}
+	}
+
+	for placed in &placed {
+		if let Some(backup) = &placed.backup {
+			remove(backup);
+		}
+		sync_dir(&placed.path);
+	}
+	Ok(())
+}
+
+fn write_synced(path: &Path, buffer: &[u8]) -> std::io::Result<()> {
+	if let Some(dir) = path.parent() {
+		fs::create_dir_all(dir)?;
+	}
+	let mut file = fs::File::create(path)?;
+	file.write_all(buffer)?;
+	file.sync_all()
+}
+
+/// Renames `temp` over `path`, linking an existing file aside first. The
+/// rename swaps the file, `path` never goes missing.
+fn place(temp: &Path, path: &Path) -> std::io::Result<Option<PathBuf>> {
+	let backup = if path.exists() {
+		let backup = sibling(path, BACKUP);
+		fs::hard_link(path, &backup)?;
+		Some(backup)
+	} else {
+		None
+	};
+	if let Err(e) = fs::rename(temp, path) {
+		if let Some(backup) = &backup {
+			remove(backup);
+		}
+		return Err(e);
+	}
+	Ok(backup)
+}
+
+fn rollback(placed: Vec<Placed>) {
+	for placed in placed.into_iter().rev() {
+		let restored = match &placed.backup {
+			Some(backup) => fs::rename(backup, &placed.path),
+			None => fs::remove_file(&placed.path),
+		};
+		if let Err(e) = restored {
+			warn!("failed to roll back {}: {}", placed.path.display(), e);
+		}
+	}
+}
+
+/// A hidden, unique name in the same directory, so the final rename never
+/// crosses file systems.
+fn sibling(path: &Path, kind: &str) -> PathBuf {
+	let name = path
+		.file_name()
+		.map(|name| name.to_string_lossy().into_owned())
+		.unwrap_or_default();
+	path.with_file_name(format!(".{}.{:016x}.{}", name, rand::random::<u64>(), kind))
+}
+
+/// Removes the temporary and backup files a crash in the middle of a
+/// [`commit`] left under `dir`. Only safe while nothing commits there.
+///
+/// Blocking, run it with `spawn_blocking`.
+pub fn clean(dir: &Path) {
+	let entries = match fs::read_dir(dir) {
+		Ok(entries) => entries,
+		Err(_) => return,
+	};
+	for entry in entries.filter_map(Result::ok) {
+		let path = entry.path();
+		match entry.file_type() {
+			Ok(kind) if kind.is_dir() => clean(&path),
+			Ok(kind) if kind.is_file() && is_leftover(&path) => {
+				warn!("removing leftover {}", path.display());
+				remove(&path);
+			},
+			_ => {},
+		}
+	}
+}
+
+/// Whether `path` is named like a [`sibling`].
+fn is_leftover(path: &Path) -> bool {
+	let name = match path.file_name().and_then(|name| name.to_str()) {
+		Some(name) if name.starts_with('.') => name,
+		_ => return false,
+	};
+	let mut parts = name.rsplitn(3, '.');
+	let kind = parts.next().unwrap_or_default();
+	let unique = parts.next().unwrap_or_default();
+	(kind == TEMP || kind == BACKUP)
+		&& unique.len() == 16
+		&& unique.bytes().all(|b| b.is_ascii_hexdigit())
+		&& parts.next().is_some()
+}
+
+fn remove(path: &Path) {
+	if let Err(e) = fs::remove_file(path) {
+		warn!("failed to clean up {}: {}", path.display(), e);
+	}
+}
+
+/// Makes the renames durable, best effort.
+fn sync_dir(path: &Path) {
+	if let Some(dir) = path.parent() {
+		if let Ok(dir) = fs::File::open(dir) {
+			let _ = dir.sync_all();
+		}
+	}
+}
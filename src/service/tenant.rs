@@ -0,0 +1,195 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use log::warn;
+use tonic::Request;
+
+use super::{
+	auth::Principal, error::MediaError, pool::WorkerPool, usage::Meter,
+	variants::Variants,
+};
+use crate::config::Config;
+
+/// Names the tenant of a call when its credential isn't bound to one.
+const TENANT: &str = "x-atwany-tenant";
+
+/// A namespace with its own storage and policies, see
+/// [`TenantConfig`](crate::config::TenantConfig).
+#[derive(Debug)]
+pub struct Tenant {
+	/// Empty for media stored without a tenant.
+	pub name: String,
+	pub config: Arc<Config>,
+	pub variants: Arc<Variants>,
+	pub usage: Arc<Meter>,
+}
+
+impl Tenant {
+	fn new(
+		name: &str,
+		config: Arc<Config>,
+		pool: Arc<WorkerPool>,
+	) -> anyhow::Result<Self> {
+		let usage = Arc::new(Meter::new(&config)?);
+		let variants = Variants::new(config.clone(), pool, usage.clone());
+		Ok(Self {
+			name: name.to_string(),
+			variants: Arc::new(variants),
+			usage,
+			config,
+		})
+	}
+
+	/// Where its media is served on the HTTP server.
+	pub fn url_prefix(&self) -> String {
+		if self.name.is_empty() {
+			String::new()
+		} else {
+			format!("/t/{}", self.name)
+		}
+	}
+
+	/// Rejects uploads of `len` bytes its policy doesn't allow.
+	pub fn check_size(&self, len: usize) -> Result<(), MediaError> {
+		let max = self.config.policy.max_upload_bytes;
+		if max > 0 && len as u64 > max {
+			return Err(MediaError::InvalidArgument(format!(
+				"upload of {} bytes exceeds the limit of {} bytes",
+				len, max
+			)));
+		}
+		Ok(())
+	}
+
+	/// Rejects images in a `format` its policy doesn't allow.
+	pub fn check_format(&self, format: &str) -> Result<(), MediaError> {
+		let allowed = &self.config.policy.allowed_formats;
+		if !allowed.is_empty() && !allowed.iter().any(|a| a == format) {
+			return Err(MediaError::InvalidArgument(format!(
+				"{} images are not accepted",
+				format
+			)));
+		}
+		Ok(())
+	}
+
+	/// Rejects files with an `ext` its policy doesn't allow.
+	pub fn check_file_extension(&self, ext: &str) -> Result<(), MediaError> {
+		let allowed = &self.config.policy.allowed_file_extensions;
+		let accepted = allowed.iter().any(|a| a.eq_ignore_ascii_case(ext));
+		if !allowed.is_empty() && !accepted {
+			return Err(MediaError::InvalidArgument(format!(
+				".{} files are not accepted",
+				ext
+			)));
+		}
+		Ok(())
+	}
+}
+
+/// Every tenant of the deployment, built once from the config.
+#[derive(Debug)]
+pub struct Tenants {
+	default: Arc<Tenant>,
+	named: HashMap<String, Arc<Tenant>>,
+}
+
+impl Tenants {
+	pub fn new(
+		config: Arc<Config>,
+		pool: Arc<WorkerPool>,
+	) -> anyhow::Result<Self> {
+		let mut named = HashMap::new();
+		for (name, tenant) in &config.tenants {
+			if !is_valid_name(name) {
+				anyhow::bail!(
+					"invalid tenant name {:?}, use a-z, 0-9, - and _",
+					name
+				);
+			}
+			let config = Arc::new(config.for_tenant(name, tenant));
+			named.insert(
+				name.clone(),
+				Arc::new(Tenant::new(name, config, pool.clone())?),
+			);
+		}
+		Ok(Self {
+			default: Arc::new(Tenant::new("", config, pool)?),
+			named,
+		})
+	}
+
+	/// Writes every tenant's usage to disk, see [`Meter::flush`].
+	///
+	/// Blocking, run it with `spawn_blocking`.
+	pub fn flush_usage(&self) {
+		for tenant in self.all() {
+			tenant.usage.flush();
+		}
+	}
+
+	/// Flushes the usage every `interval` until the process exits.
+	pub async fn flush_usage_every(self: Arc<Self>, interval: Duration) {
+		let mut ticker = tokio::time::interval(interval);
+		loop {
+			ticker.tick().await;
+			let tenants = self.clone();
+			let flush = move || tenants.flush_usage();
+			if tokio::task::spawn_blocking(flush).await.is_err() {
+				warn!("Flushing the usage panicked");
+			}
+		}
+	}
+
+	/// The tenant called `name`, the empty name is the default tenant.
+	pub fn get(&self, name: &str) -> Option<Arc<Tenant>> {
+		if name.is_empty() {
+			return Some(self.default_tenant());
+		}
+		self.named.get(name).cloned()
+	}
+
+	/// Where media without a tenant lives.
+	pub fn default_tenant(&self) -> Arc<Tenant> { self.default.clone() }
+
+	/// The default tenant first, then the others by name.
+	pub fn all(&self) -> Vec<Arc<Tenant>> {
+		let mut named: Vec<_> = self.named.values().cloned().collect();
+		named.sort_by(|a, b| a.name.cmp(&b.name));
+		std::iter::once(self.default.clone()).chain(named).collect()
+	}
+
+	/// The tenant a gRPC call acts for, the one its credential is bound to
+	/// or else the one it names.
+	pub fn of<T>(
+		&self,
+		request: &Request<T>,
+	) -> Result<Arc<Tenant>, MediaError> {
+		let principal = Principal::of(request);
+		let requested = request
+			.metadata()
+			.get(TENANT)
+			.and_then(|name| name.to_str().ok())
+			.filter(|name| !name.is_empty());
+		let name = match (principal.tenant.as_deref(), requested) {
+			(Some(bound), Some(requested)) if bound != requested => {
+				return Err(MediaError::PermissionDenied(format!(
+					"{} can't act for tenant {}",
+					principal, requested
+				)))
+			},
+			(Some(bound), _) => bound,
+			(None, requested) => requested.unwrap_or(""),
+		};
+		self.get(name).ok_or_else(|| {
+			MediaError::PermissionDenied(format!("unknown tenant {:?}", name))
+		})
+	}
+}
+
+/// Tenant names end up in paths and URLs.
+fn is_valid_name(name: &str) -> bool {
+	!name.is_empty()
+		&& name.bytes().all(|b| {
+			matches!(b, b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_')
+		})
+}
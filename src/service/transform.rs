@@ -0,0 +1,122 @@
+use image::{DynamicImage, GenericImageView};
+
+use super::{
+	decode::decode,
+	encode::{get_avif_bytes, get_image_bytes, get_png_bytes, get_webp_bytes},
+	error::MediaError,
+	optimize::optimize_png,
+	resize::resize,
+};
+use crate::config::{Config, OutputFormat};
+
+/// How a transformed image fills the requested box.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Fit {
+	/// Fits inside the box, keeping the aspect ratio.
+	Contain,
+	/// Covers the box, keeping the aspect ratio and cropping the overflow
+	/// around the center.
+	Cover,
+	/// Stretched to the box.
+	Fill,
+}
+
+impl Fit {
+	pub fn parse(name: &str) -> Option<Self> {
+		match name {
+			"contain" => Some(Fit::Contain),
+			"cover" => Some(Fit::Cover),
+			"fill" => Some(Fit::Fill),
+			_ => None,
+		}
+	}
+
+	pub const fn name(self) -> &'static str {
+		match self {
+			Fit::Contain => "contain",
+			Fit::Cover => "cover",
+			Fit::Fill => "fill",
+		}
+	}
+}
+
+/// An arbitrary rendition of an original. A missing dimension follows from
+/// the other one and the aspect ratio, images are never enlarged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Spec {
+	pub width: Option<u32>,
+	pub height: Option<u32>,
+	pub fit: Fit,
+	pub format: OutputFormat,
+	pub quality: u8,
+}
+
+/// Decodes `original` and renders it as `spec` asks. Blocking, meant to run
+/// on the [`WorkerPool`](super::WorkerPool).
+pub fn render(
+	original: Vec<u8>,
+	spec: &Spec,
+	config: &Config,
+) -> Result<Vec<u8>, MediaError> {
+	// a scaled JPEG decode covers anything that fits inside the box
+	let max_dim = match (spec.fit, spec.width, spec.height) {
+		(Fit::Contain, Some(width), Some(height)) => Some(width.max(height)),
+		_ => None,
+	};
+	let source = decode(original, max_dim).map_err(MediaError::Decode)?;
+	let image = transform(&source.image, (source.width, source.height), spec);
+	let quality = spec.quality.clamp(1, 100);
+	let buffer = match spec.format {
+		OutputFormat::Jpeg => {
+			get_image_bytes(&image, quality, &config.encoding.jpeg)
+		},
+		OutputFormat::Png => get_png_bytes(&image)
+			.map(|buffer| optimize_png(buffer, &config.png).buffer),
+		OutputFormat::Webp => Ok(get_webp_bytes(&image, quality)),
+		OutputFormat::Avif => {
+			get_avif_bytes(&image, quality, config.encoding.avif_speed)
+		},
+	};
+	buffer.map_err(MediaError::Render)
+}
+
+/// `original` is the size of the upload, `image` may have been decoded at a
+/// reduced scale.
+fn transform(
+	image: &DynamicImage,
+	original: (u32, u32),
+	spec: &Spec,
+) -> DynamicImage {
+	let (width, height) = (f64::from(original.0), f64::from(original.1));
+	// never enlarge
+	let box_width = spec.width.map(|w| f64::from(w).min(width));
+	let box_height = spec.height.map(|h| f64::from(h).min(height));
+	let (box_width, box_height) = match (box_width, box_height) {
+		(Some(w), Some(h)) => (w, h),
+		(Some(w), None) => (w, height * w / width),
+		(None, Some(h)) => (width * h / height, h),
+		(None, None) => (width, height),
+	};
+	let px = |v: f64| (v.round() as u32).max(1);
+	match spec.fit {
+		Fit::Fill => resize(image, px(box_width), px(box_height)),
+		Fit::Contain => {
+			let scale = (box_width / width).min(box_height / height);
+			resize(image, px(width * scale), px(height * scale))
+		},
+		Fit::Cover => {
+			let scale = (box_width / width).max(box_height / height);
+			let resized = resize(image, px(width * scale), px(height * scale));
+			let (crop_width, crop_height) = (
+				px(box_width).min(resized.width()),
+				px(box_height).min(resized.height()),
+			);
+			resized.crop_imm(
+				(resized.width() - crop_width) / 2,
+				(resized.height() - crop_height) / 2,
+				crop_width,
+				crop_height,
+			)
+		},
+	}
+}
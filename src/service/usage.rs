@@ -0,0 +1,338 @@
+use std::{
+	fs, io, mem,
+	path::{Path, PathBuf},
+	sync::{Arc, Mutex, MutexGuard},
+	time::Instant,
+};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use super::{
+	error::MediaError,
+	pool::WorkerPool,
+	storage::{self, Pending},
+};
+use crate::config::{Config, QuotaConfig, TENANTS_DIR};
+
+/// Kept in the tenant's images directory. Hidden files are neither served
+/// nor taken for media.
+const USAGE_FILE: &str = ".usage.toml";
+
+/// What a tenant stores and the processing it used, see [`QuotaConfig`].
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Usage {
+	pub bytes: u64,
+	pub objects: u64,
+	pub cpu_seconds: f64,
+}
+
+impl Usage {
+	/// The usage stored at `path`, `None` when there is none yet.
+	fn load(path: &Path) -> Result<Option<Self>, MediaError> {
+		let raw = match fs::read_to_string(path) {
+			Ok(raw) => raw,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+			Err(e) => return Err(MediaError::storage(path, e)),
+		};
+		toml::from_str(&raw).map(Some).map_err(|e| {
+			let e = io::Error::new(io::ErrorKind::InvalidData, e);
+			MediaError::storage(path, e)
+		})
+	}
+
+	fn apply(self, delta: &Delta) -> Self {
+		Self {
+			bytes: (self.bytes + delta.added_bytes)
+				.saturating_sub(delta.freed_bytes),
+			objects: (self.objects + delta.added_objects)
+				.saturating_sub(delta.freed_objects),
+			cpu_seconds: self.cpu_seconds + delta.cpu_seconds,
+		}
+	}
+
+	/// Name, amount used, soft and hard limit of every resource.
+	const fn limits(
+		&self,
+		quota: &QuotaConfig,
+	) -> [(&'static str, u64, u64, u64); 3] {
+		[
+			("bytes", self.bytes, quota.soft_bytes, quota.hard_bytes),
+			("objects", self.objects, quota.soft_objects, quota.hard_objects),
+			(
+				"cpu_seconds",
+				self.cpu_seconds as u64,
+				quota.soft_cpu_seconds,
+				quota.hard_cpu_seconds,
+			),
+		]
+	}
+}
+
+/// A change to a tenant's usage.
+#[derive(Debug, Clone, Copy, Default)]
+struct Delta {
+	added_bytes: u64,
+	freed_bytes: u64,
+	added_objects: u64,
+	freed_objects: u64,
+	cpu_seconds: f64,
+}
+
+impl Delta {
+	fn add(&mut self, other: &Self) {
+		self.added_bytes += other.added_bytes;
+		self.freed_bytes += other.freed_bytes;
+		self.added_objects += other.added_objects;
+		self.freed_objects += other.freed_objects;
+		self.cpu_seconds += other.cpu_seconds;
+	}
+
+	fn is_empty(&self) -> bool {
+		self.added_bytes == 0
+			&& self.freed_bytes == 0
+			&& self.added_objects == 0
+			&& self.freed_objects == 0
+			&& self.cpu_seconds == 0.0
+	}
+}
+
+#[derive(Debug)]
+struct State {
+	/// As of the last flush, with the changes since.
+	usage: Usage,
+	/// Changes not written to disk yet.
+	unflushed: Delta,
+}
+
+/// Accounts for what a tenant stores and processes, and holds it to its
+/// quota. Usage is kept in memory and [`flush`](Self::flush)ed to disk
+/// periodically, on top of what the disk holds, so a `reprocess` run from
+/// the command line adds to the server's figures instead of overwriting
+/// them. Checks happen before a write and concurrent uploads can overshoot a
+/// hard limit by the ones in flight.
+#[derive(Debug)]
+pub struct Meter {
+	path: PathBuf,
+	quota: QuotaConfig,
+	state: Mutex<State>,
+	/// Held while flushing, so flushes don't undo each other.
+	flushing: Mutex<()>,
+}
+
+impl Meter {
+	/// Loads the tenant's usage, counting what is already stored the first
+	/// time. Fails on a usage file that can't be read rather than start
+	/// over from zero.
+	pub fn new(config: &Config) -> anyhow::Result<Self> {
+		let path = config.storage.images_dir.join(USAGE_FILE);
+		let stored = Usage::load(&path)?;
+		let meter = Self {
+			state: Mutex::new(State {
+				usage: stored.unwrap_or_default(),
+				unflushed: Delta::default(),
+			}),
+			quota: config.policy.quota,
+			flushing: Mutex::new(()),
+			path,
+		};
+		if stored.is_none() && config.storage.images_dir.exists() {
+			let storage = &config.storage;
+			let mut delta = Delta::default();
+			for dir in &[&storage.images_dir, &storage.files_dir] {
+				count(dir, &dir.join(TENANTS_DIR), &mut delta);
+			}
+			info!(
+				"Counted {} stored objects, {} bytes, in {}",
+				delta.added_objects,
+				delta.added_bytes,
+				storage.images_dir.display()
+			);
+			meter.record(&delta);
+			meter.flush();
+		}
+		Ok(meter)
+	}
+
+	pub const fn quota(&self) -> &QuotaConfig { &self.quota }
+
+	pub fn usage(&self) -> Usage { self.lock().usage }
+
+	/// Resources used beyond their soft limit.
+	pub fn warnings(&self) -> Vec<&'static str> {
+		self.usage()
+			.limits(&self.quota)
+			.iter()
+			.filter(|&&(_, used, soft, _)| soft > 0 && used > soft)
+			.map(|&(resource, ..)| resource)
+			.collect()
+	}
+
+	/// Turns work away before it is done once a hard limit is reached.
+	pub fn admit(&self) -> Result<(), MediaError> {
+		let limits = self.usage().limits(&self.quota);
+		for &(resource, used, _, hard) in &limits {
+			if hard > 0 && used >= hard {
+				return Err(MediaError::QuotaExceeded {
+					resource,
+					used,
+					limit: hard,
+				});
+			}
+		}
+		Ok(())
+	}
+
+	/// Runs `job` on `pool` and charges the time it took on the worker, close
+	/// to the CPU time of jobs that are mostly single threaded.
+	pub async fn run<F, T>(
+		self: &Arc<Self>,
+		pool: &WorkerPool,
+		job: F,
+	) -> Result<T, MediaError>
+	where
+		F: FnOnce() -> T + Send + 'static,
+		T: Send + 'static,
+	{
+		let meter = self.clone();
+		pool.run(move || {
+			let started = Instant::now();
+			let result = job();
+			meter.record(&Delta {
+				cpu_seconds: started.elapsed().as_secs_f64(),
+				..Delta::default()
+			});
+			result
+		})
+		.await
+	}
+
+	/// Writes `files` like [`storage::commit`] and accounts for them, unless
+	/// that would take the tenant past a hard limit. Files they replace are
+	/// deducted.
+	///
+	/// Blocking, run it with `spawn_blocking`.
+	pub fn commit(&self, files: Vec<Pending>) -> Result<(), MediaError> {
+		let mut delta = Delta::default();
+		for file in &files {
+			match fs::metadata(&file.path) {
+				Ok(replaced) => delta.freed_bytes += replaced.len(),
+				Err(_) => delta.added_objects += 1,
+			}
+			delta.added_bytes += file.buffer.len() as u64;
+		}
+		let before = self.usage();
+		let after = before.apply(&delta).limits(&self.quota);
+		for (&(resource, used, _, hard), &(.., grown, _, _)) in
+			before.limits(&self.quota).iter().zip(&after)
+		{
+			// shrinking is always allowed, even past the limit
+			if hard > 0 && grown > hard && grown > used {
+				return Err(MediaError::QuotaExceeded {
+					resource,
+					used,
+					limit: hard,
+				});
+			}
+		}
+		storage::commit(files)?;
+		self.record(&delta);
+		Ok(())
+	}
+
+	/// Removes `paths` and deducts them. Files that can't be removed are
+	/// logged and kept.
+	///
+	/// Blocking, run it with `spawn_blocking`.
+	pub fn remove(&self, paths: &[PathBuf]) {
+		let mut delta = Delta::default();
+		for path in paths {
+			let len = match fs::metadata(path) {
+				Ok(metadata) => metadata.len(),
+				Err(_) => continue,
+			};
+			match fs::remove_file(path) {
+				Ok(()) => {
+					delta.freed_bytes += len;
+					delta.freed_objects += 1;
+				},
+				Err(e) => warn!("failed to remove {}: {}", path.display(), e),
+			}
+		}
+		self.record(&delta);
+	}
+
+	/// Writes the changes since the last flush to disk, applied to the
+	/// usage found there. When that fails the file is left alone, the
+	/// changes are kept for the next flush and the failure is logged.
+	///
+	/// Blocking, run it with `spawn_blocking`.
+	pub fn flush(&self) {
+		let _flushing = self
+			.flushing
+			.lock()
+			.unwrap_or_else(|poisoned| poisoned.into_inner());
+		let unflushed = mem::take(&mut self.lock().unflushed);
+		if unflushed.is_empty() {
+			return;
+		}
+		let written = Usage::load(&self.path).and_then(|stored| {
+			let updated = stored.unwrap_or_default().apply(&unflushed);
+			// plain numbers always serialize
+			let buffer = toml::to_string(&updated).unwrap_or_default();
+			let file = Pending {
+				path: self.path.clone(),
+				buffer: buffer.into_bytes(),
+			};
+			storage::commit(vec![file])?;
+			Ok(updated)
+		});
+		let mut state = self.lock();
+		match written {
+			// with what was recorded while writing
+			Ok(updated) => state.usage = updated.apply(&state.unflushed),
+			Err(e) => {
+				warn!("failed to record usage: {}", e);
+				state.unflushed.add(&unflushed);
+			},
+		}
+	}
+
+	/// Applies `delta` in memory, the next [`flush`](Self::flush) writes it.
+	fn record(&self, delta: &Delta) {
+		let mut state = self.lock();
+		state.usage = state.usage.apply(delta);
+		state.unflushed.add(delta);
+	}
+
+	fn lock(&self) -> MutexGuard<'_, State> {
+		self.state
+			.lock()
+			.unwrap_or_else(|poisoned| poisoned.into_inner())
+	}
+}
+
+/// Adds every stored file under `dir` to `delta`, leaving out hidden files
+/// and the other tenants' directory `skip`.
+fn count(dir: &Path, skip: &Path, delta: &mut Delta) {
+	let entries = match fs::read_dir(dir) {
+		Ok(entries) => entries,
+		Err(_) => return,
+	};
+	for entry in entries.filter_map(Result::ok) {
+		let path = entry.path();
+		let hidden = entry.file_name().to_string_lossy().starts_with('.');
+		if hidden || path == skip {
+			continue;
+		}
+		match entry.metadata() {
+			Ok(metadata) if metadata.is_dir() => count(&path, skip, delta),
+			Ok(metadata) => {
+				delta.added_bytes += metadata.len();
+				delta.added_objects += 1;
+			},
+			Err(_) => {},
+		}
+	}
+}
@@ -0,0 +1,336 @@
+use std::{
+	collections::HashMap,
+	path::PathBuf,
+	sync::{Arc, Mutex},
+};
+
+use log::debug;
+
+use super::{
+	error::MediaError,
+	layout::{check_id, image_path, manifest_path},
+	limit::LoadShedder,
+	manifest::Manifest,
+	media::{decode_image, process},
+	meta::{mime_type, sha256_hex},
+	pool::WorkerPool,
+	storage::Pending,
+	usage::Meter,
+};
+use crate::{
+	config::{size_key, Config, OriginalMode, OutputFormat},
+	pb::atwany::media::Size,
+};
+
+/// Extensions tried for variants that predate manifests.
+const EXTENSIONS: [&str; 5] = ["jpeg", "png", "jpg", "gif", "webp"];
+
+/// A stored variant, read back for a client.
+#[derive(Debug, Clone)]
+pub struct Stored {
+	pub buffer: Vec<u8>,
+	pub extension: String,
+	pub mime_type: &'static str,
+	pub sha256: String,
+	/// It did not exist and was rendered for this request.
+	pub generated: bool,
+}
+
+/// Reads variants from storage, rendering the ones that were never generated,
+/// or not in the format asked for, from the stored original. Renders of the
+/// same media are serialized, so concurrent requests for a missing variant
+/// render it once and the others read the stored result.
+#[derive(Debug)]
+pub struct Variants {
+	config: Arc<Config>,
+	pool: Arc<WorkerPool>,
+	usage: Arc<Meter>,
+	rendering: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl Variants {
+	pub fn new(
+		config: Arc<Config>,
+		pool: Arc<WorkerPool>,
+		usage: Arc<Meter>,
+	) -> Self {
+		Self {
+			config,
+			pool,
+			usage,
+			rendering: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// The `size` variant of `id`, rendered when missing if `shedder`
+	/// admits it.
+	pub async fn get(
+		&self,
+		id: &str,
+		size: Size,
+		shedder: &LoadShedder,
+	) -> Result<Stored, MediaError> {
+		self.fetch(id, size, None, shedder).await
+	}
+
+	/// The `size` variant in `format` rather than its own, rendered and kept
+	/// next to it the first time. Originals are only served as stored, and
+	/// so is the variant when it can't be had in `format`.
+	pub async fn get_as(
+		&self,
+		id: &str,
+		size: Size,
+		format: OutputFormat,
+		shedder: &LoadShedder,
+	) -> Result<Stored, MediaError> {
+		if size == Size::Original {
+			return self.fetch(id, size, None, shedder).await;
+		}
+		match self.fetch(id, size, Some(format), shedder).await {
+			Ok(stored) => Ok(stored),
+			Err(e) => {
+				let ext = format.extension();
+				debug!("serving {} as stored rather than {}: {}", id, ext, e);
+				self.fetch(id, size, None, shedder).await
+			},
+		}
+	}
+
+	async fn fetch(
+		&self,
+		id: &str,
+		size: Size,
+		format: Option<OutputFormat>,
+		shedder: &LoadShedder,
+	) -> Result<Stored, MediaError> {
+		check_id(id)?;
+		if let Some(stored) = self.read(id, size, format).await? {
+			return Ok(stored);
+		}
+		if size == Size::Original {
+			return Err(MediaError::NotFound(id.to_string()));
+		}
+
+		let lock = self.lock(id);
+		let guard = lock.lock().await;
+		let rendered = match self.read(id, size, format).await {
+			// rendered while we were waiting
+			Ok(Some(stored)) => Ok(stored),
+			Ok(None) => match shedder.admit() {
+				Ok(()) => self.render(id, size, format).await,
+				Err(e) => Err(e),
+			},
+			Err(e) => Err(e),
+		};
+		drop(guard);
+		self.release(id, lock);
+		rendered
+	}
+
+	/// Checksum the manifest of `id` records for `size`, without touching the
+	/// variant itself.
+	pub async fn recorded_sha256(&self, id: &str, size: Size) -> Option<String> {
+		let manifest = self.manifest(id).await;
+		manifest.get(size).map(|entry| entry.sha256.clone())
+	}
+
+	/// Whether `id` was uploaded as private media. Fails rather than guess
+	/// when its manifest can't be read.
+	pub async fn is_private(&self, id: &str) -> Result<bool, MediaError> {
+		let config = self.config.clone();
+		let key = id.to_string();
+		let manifest = tokio::task::spawn_blocking(move || {
+			Manifest::load(&manifest_path(&config.storage, &key))
+		})
+		.await
+		.map_err(|_| MediaError::WorkerFailed)??;
+		Ok(manifest.private)
+	}
+
+	async fn manifest(&self, id: &str) -> Manifest {
+		let config = self.config.clone();
+		let key = id.to_string();
+		tokio::task::spawn_blocking(move || {
+			Manifest::read(&manifest_path(&config.storage, &key))
+		})
+		.await
+		.unwrap_or_default()
+	}
+
+	async fn read(
+		&self,
+		id: &str,
+		size: Size,
+		format: Option<OutputFormat>,
+	) -> Result<Option<Stored>, MediaError> {
+		let config = self.config.clone();
+		let key = id.to_string();
+		let found = tokio::task::spawn_blocking(move || {
+			let manifest = Manifest::read(&manifest_path(&config.storage, &key));
+			let found = match format {
+				Some(format) => {
+					let ext = format.extension();
+					let path = image_path(&config.storage, &key, size, ext);
+					if path.exists() {
+						Some((path, ext.to_string()))
+					} else {
+						None
+					}
+				},
+				None => find(&config, &manifest, &key, size),
+			};
+			found.map(|path| (manifest, path))
+		})
+		.await
+		.map_err(|_| MediaError::WorkerFailed)?;
+		let (manifest, (path, extension)) = match found {
+			Some(found) => found,
+			None => return Ok(None),
+		};
+		let buffer = match tokio::fs::read(&path).await {
+			Ok(buffer) => buffer,
+			// removed since we looked
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+				return Ok(None)
+			},
+			Err(e) => return Err(MediaError::storage(&path, e)),
+		};
+		debug!("read {} for {}", path.display(), id);
+		let recorded = match manifest.get(size) {
+			Some(entry) if entry.extension == extension => Some(entry),
+			_ => manifest.get_alternate(size, &extension),
+		};
+		let sha256 = match recorded {
+			Some(entry) => entry.sha256.clone(),
+			None => sha256_hex(&buffer),
+		};
+		Ok(Some(Stored {
+			buffer,
+			mime_type: mime_type(&extension),
+			extension,
+			sha256,
+			generated: false,
+		}))
+	}
+
+	async fn render(
+		&self,
+		id: &str,
+		size: Size,
+		format: Option<OutputFormat>,
+	) -> Result<Stored, MediaError> {
+		let original =
+			self.read(id, Size::Original, None).await?.ok_or_else(|| {
+				MediaError::NotFound(id.to_string())
+			})?;
+		// only pixels for the requested variant are needed
+		let source = decode_image(
+			&self.pool,
+			&self.usage,
+			original.buffer,
+			OriginalMode::Passthrough,
+		)
+		.await?;
+		// an alternate format is rendered like the variant would be in it
+		let alternate = format
+			.filter(|format| *format != self.config.encoding.variant(size).format);
+		let config = match alternate {
+			Some(format) => {
+				let mut config = (*self.config).clone();
+				let mut variant = config.encoding.variant(size);
+				variant.format = format;
+				config.encoding.variants.insert(size_key(size).to_string(), variant);
+				Arc::new(config)
+			},
+			None => self.config.clone(),
+		};
+		let render_config = config.clone();
+		let responses = self
+			.usage
+			.run(&self.pool, move || process(&source, &render_config, &[size]))
+			.await??;
+		let res = responses
+			.into_iter()
+			.find(|res| res.size == i32::from(size))
+			.ok_or_else(|| {
+				let size = size_key(size);
+				let msg = format!("{} of {} wasn't rendered", size, id);
+				MediaError::Internal(msg)
+			})?;
+
+		let key = id.to_string();
+		let stored = Stored {
+			buffer: res.buffer.clone(),
+			extension: res.file_extension.clone(),
+			mime_type: mime_type(&res.file_extension),
+			sha256: res.sha256.clone(),
+			generated: true,
+		};
+		let usage = self.usage.clone();
+		let committed = tokio::task::spawn_blocking(move || {
+			let storage = &config.storage;
+			let manifest_path = manifest_path(storage, &key);
+			let mut manifest = Manifest::load(&manifest_path)?;
+			match alternate {
+				Some(_) => manifest.record_alternate(&res, &config),
+				None => manifest.record(std::slice::from_ref(&res), &config),
+			}
+			usage.commit(vec![
+				Pending {
+					path: image_path(storage, &key, size, &res.file_extension),
+					buffer: res.buffer,
+				},
+				manifest.to_pending(&manifest_path),
+			])
+		})
+		.await
+		.map_err(|_| MediaError::WorkerFailed)?;
+		match committed {
+			// served all the same, it is rendered again next time
+			Err(MediaError::QuotaExceeded { .. }) => {
+				let size = size_key(size);
+				debug!("quota exceeded, not storing {} of {}", size, id);
+			},
+			committed => committed?,
+		}
+		Ok(stored)
+	}
+
+	fn lock(&self, id: &str) -> Arc<tokio::sync::Mutex<()>> {
+		let mut rendering = self
+			.rendering
+			.lock()
+			.unwrap_or_else(|poisoned| poisoned.into_inner());
+		rendering.entry(id.to_string()).or_default().clone()
+	}
+
+	/// Forgets the lock of `id` once nobody else is waiting on it.
+	fn release(&self, id: &str, lock: Arc<tokio::sync::Mutex<()>>) {
+		let mut rendering = self
+			.rendering
+			.lock()
+			.unwrap_or_else(|poisoned| poisoned.into_inner());
+		// one reference in the map, one here
+		if Arc::strong_count(&lock) <= 2 {
+			rendering.remove(id);
+		}
+	}
+}
+
+/// Path and extension of a stored variant, preferring the one recorded in
+/// the manifest.
+fn find(
+	config: &Config,
+	manifest: &Manifest,
+	id: &str,
+	size: Size,
+) -> Option<(PathBuf, String)> {
+	let recorded = manifest.get(size).map(|entry| entry.extension.clone());
+	let configured = config.encoding.variant(size).format.extension();
+	recorded
+		.into_iter()
+		.chain(std::iter::once(configured.to_string()))
+		.chain(EXTENSIONS.iter().map(|ext| ext.to_string()))
+		.map(|ext| (image_path(&config.storage, id, size, &ext), ext))
+		.find(|(path, _)| path.exists())
+}
@@ -0,0 +1,265 @@
+use anyhow::{anyhow, Context as _};
+use futures::{channel::mpsc, SinkExt};
+use log::{debug, error, info, warn};
+use rustls::{
+    internal::pemfile, AllowAnyAuthenticatedClient, Certificate,
+    NoClientAuth, PrivateKey, RootCertStore, ServerConfig,
+};
+use std::{
+    fs::{self, File},
+    future::Future,
+    io::{self, BufReader},
+    net::SocketAddr,
+    path::Path,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tonic::transport::server::Connected;
+
+use crate::config::TlsConfig;
+
+/// Established connections waiting for the server to pick them up.
+const BACKLOG: usize = 64;
+/// Handshakes in progress before new connections wait in the listen queue.
+const MAX_HANDSHAKES: usize = 256;
+/// Clients that don't finish their handshake by then are dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Current = Arc<RwLock<Arc<ServerConfig>>>;
+
+type Incoming = mpsc::Receiver<io::Result<Tracked>>;
+
+/// Accepts TLS connections on `addr` for `serve_with_incoming` until
+/// `shutdown` completes, the stream ends then. Every handshake uses the
+/// certificate loaded last, see [`TlsConfig`](crate::config::TlsConfig).
+pub async fn incoming(
+    addr: SocketAddr,
+    config: &TlsConfig,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> anyhow::Result<(Incoming, Connections)> {
+    let current: Current = Arc::new(RwLock::new(load(config)?));
+    if config.reload_interval_secs > 0 {
+        tokio::spawn(watch(config.clone(), current.clone()));
+    }
+    let mut listener = TcpListener::bind(addr).await?;
+    let (tx, rx) = mpsc::channel(BACKLOG);
+    let connections = Connections::default();
+    let open = connections.0.clone();
+    let handshakes = Arc::new(Semaphore::new(MAX_HANDSHAKES));
+    tokio::spawn(async move {
+        tokio::pin!(shutdown);
+        while !tx.is_closed() {
+            let permit = tokio::select! {
+                permit = handshakes.clone().acquire_owned() => permit,
+                _ = &mut shutdown => break,
+            };
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = &mut shutdown => break,
+            };
+            let (stream, peer) = match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // usually out of file descriptors, give them time to close
+                    warn!("Failed to accept a connection: {}", e);
+                    tokio::time::delay_for(Duration::from_millis(100)).await;
+                    continue;
+                },
+            };
+            if let Err(e) = stream.set_nodelay(true) {
+                debug!("Failed to set TCP_NODELAY for {}: {}", peer, e);
+            }
+            let server = current
+                .read()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .clone();
+            let acceptor = TlsAcceptor::from(server);
+            let open = open.clone();
+            let mut tx = tx.clone();
+            // a slow handshake never holds up the others
+            tokio::spawn(async move {
+                let handshake = tokio::time::timeout(
+                    HANDSHAKE_TIMEOUT,
+                    acceptor.accept(stream),
+                )
+                .await;
+                drop(permit);
+                match handshake {
+                    Ok(Ok(stream)) => {
+                        let stream = Tracked { stream, _open: open };
+                        let _ = tx.send(Ok(stream)).await;
+                    },
+                    Ok(Err(e)) => {
+                        debug!("TLS handshake with {} failed: {}", peer, e)
+                    },
+                    Err(_) => debug!("TLS handshake with {} timed out", peer),
+                }
+            });
+        }
+        info!("Stopped accepting gRPC connections");
+    });
+    Ok((rx, connections))
+}
+
+/// The connections [`incoming`] accepted that are still open.
+#[derive(Debug, Default)]
+pub struct Connections(Arc<()>);
+
+impl Connections {
+    /// Waits for the open connections to close, so calls in flight when
+    /// the server stopped accepting get to finish, for at most `timeout`.
+    pub async fn drain(self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        // one reference is ours
+        while Arc::strong_count(&self.0) > 1 {
+            if Instant::now() >= deadline {
+                let open = Arc::strong_count(&self.0) - 1;
+                warn!("Closing {} connections that are still open", open);
+                return;
+            }
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+        }
+    }
+}
+
+/// A TLS connection, counted in [`Connections`] until it is dropped.
+#[derive(Debug)]
+pub struct Tracked {
+    stream: TlsStream<TcpStream>,
+    _open: Arc<()>,
+}
+
+impl AsyncRead for Tracked {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Tracked {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+impl Connected for Tracked {
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        self.stream.get_ref().0.peer_addr().ok()
+    }
+}
+
+/// Swaps in the files once they change. A broken set, e.g. a certificate
+/// written before its key, keeps the previous one and is retried.
+async fn watch(config: TlsConfig, current: Current) {
+    let interval = Duration::from_secs(config.reload_interval_secs);
+    let mut loaded = modified(&config);
+    loop {
+        tokio::time::delay_for(interval).await;
+        let stamps = modified(&config);
+        if stamps == loaded {
+            continue;
+        }
+        match load(&config) {
+            Ok(server) => {
+                *current
+                    .write()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()) = server;
+                loaded = stamps;
+                info!("Reloaded the TLS certificate");
+            },
+            Err(e) => error!("Keeping the current TLS certificate: {:#}", e),
+        }
+    }
+}
+
+fn modified(config: &TlsConfig) -> Vec<Option<SystemTime>> {
+    let mut paths = vec![config.cert_file.as_path(), config.key_file.as_path()];
+    paths.extend(config.client_ca_file.as_deref());
+    paths
+        .into_iter()
+        .map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .collect()
+}
+
+fn load(config: &TlsConfig) -> anyhow::Result<Arc<ServerConfig>> {
+    let certs = certs(&config.cert_file)?;
+    let key = private_key(&config.key_file)?;
+    let verifier = match &config.client_ca_file {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            let (added, _) = roots
+                .add_pem_file(&mut reader(path)?)
+                .map_err(|_| anyhow!("{}: invalid PEM", path.display()))?;
+            if added == 0 {
+                anyhow::bail!("{}: no CA certificate", path.display());
+            }
+            AllowAnyAuthenticatedClient::new(roots)
+        },
+        None => NoClientAuth::new(),
+    };
+    let mut server = ServerConfig::new(verifier);
+    server.set_single_cert(certs, key).map_err(|e| {
+        anyhow!("{}: {}", config.cert_file.display(), e)
+    })?;
+    // gRPC runs over HTTP/2 only
+    server.set_protocols(&[b"h2".to_vec()]);
+    Ok(Arc::new(server))
+}
+
+fn certs(path: &Path) -> anyhow::Result<Vec<Certificate>> {
+    let certs = pemfile::certs(&mut reader(path)?)
+        .map_err(|_| anyhow!("{}: invalid PEM", path.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("{}: no certificate", path.display());
+    }
+    Ok(certs)
+}
+
+/// The first PKCS#8 key, or else the first RSA one.
+fn private_key(path: &Path) -> anyhow::Result<PrivateKey> {
+    let invalid = || anyhow!("{}: invalid PEM", path.display());
+    let pkcs8 =
+        pemfile::pkcs8_private_keys(&mut reader(path)?).map_err(|_| invalid())?;
+    let rsa =
+        pemfile::rsa_private_keys(&mut reader(path)?).map_err(|_| invalid())?;
+    pkcs8
+        .into_iter()
+        .chain(rsa)
+        .next()
+        .ok_or_else(|| anyhow!("{}: no private key", path.display()))
+}
+
+fn reader(path: &Path) -> anyhow::Result<BufReader<File>> {
+    let file = File::open(path)
+        .with_context(|| format!("reading {}", path.display()))?;
+    Ok(BufReader::new(file))
+}